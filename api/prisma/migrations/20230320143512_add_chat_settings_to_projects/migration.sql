@@ -0,0 +1,4 @@
+-- AlterTable
+ALTER TABLE `Project` ADD COLUMN `chatModel` VARCHAR(191) NOT NULL DEFAULT 'gpt-3.5-turbo',
+    ADD COLUMN `maxTokens` INTEGER NULL,
+    ADD COLUMN `temperature` DOUBLE NOT NULL DEFAULT 0.5;
@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE `Project` ADD COLUMN `keywordWeight` DOUBLE NOT NULL DEFAULT 0.3;
@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE `Project` ADD COLUMN `contextTokens` INTEGER NOT NULL DEFAULT 2000;
@@ -0,0 +1,23 @@
+-- AlterTable
+ALTER TABLE `Project` MODIFY `status` ENUM('Pending', 'Training', 'Trained', 'Failed') NOT NULL DEFAULT 'Pending';
+
+-- CreateTable
+CREATE TABLE `TrainingJob` (
+    `id` VARCHAR(191) NOT NULL,
+    `projectId` VARCHAR(191) NOT NULL,
+    `source` JSON NOT NULL,
+    `status` ENUM('Queued', 'Running', 'Succeeded', 'Failed') NOT NULL DEFAULT 'Queued',
+    `stage` ENUM('Fetching', 'Parsing', 'Embedding') NULL,
+    `documents` INTEGER NULL,
+    `sections` INTEGER NULL,
+    `error` TEXT NULL,
+    `startedAt` DATETIME(3) NULL,
+    `finishedAt` DATETIME(3) NULL,
+    `createdAt` DATETIME(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3),
+    `updatedAt` DATETIME(3) NOT NULL,
+
+    PRIMARY KEY (`id`)
+) DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- AddForeignKey
+ALTER TABLE `TrainingJob` ADD CONSTRAINT `TrainingJob_projectId_fkey` FOREIGN KEY (`projectId`) REFERENCES `Project`(`id`) ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,17 @@
+-- AlterTable
+ALTER TABLE `TrainingJob` RENAME COLUMN `source` TO `sources`;
+
+-- CreateTable
+CREATE TABLE `Source` (
+    `id` VARCHAR(191) NOT NULL,
+    `projectId` VARCHAR(191) NOT NULL,
+    `kind` ENUM('GitHub', 'Website', 'Archive') NOT NULL,
+    `config` JSON NOT NULL,
+    `createdAt` DATETIME(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3),
+    `updatedAt` DATETIME(3) NOT NULL,
+
+    PRIMARY KEY (`id`)
+) DEFAULT CHARACTER SET utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- AddForeignKey
+ALTER TABLE `Source` ADD CONSTRAINT `Source_projectId_fkey` FOREIGN KEY (`projectId`) REFERENCES `Project`(`id`) ON DELETE CASCADE ON UPDATE CASCADE;
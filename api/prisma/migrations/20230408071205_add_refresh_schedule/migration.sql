@@ -0,0 +1,8 @@
+-- AlterTable
+ALTER TABLE `Project` ADD COLUMN `refreshInterval` INTEGER NULL,
+    ADD COLUMN `nextRefreshAt` DATETIME(3) NULL,
+    ADD COLUMN `trainedAt` DATETIME(3) NULL,
+    ADD COLUMN `manifest` JSON NULL;
+
+-- AlterTable
+ALTER TABLE `TrainingJob` ADD COLUMN `trigger` ENUM('Manual', 'Scheduled') NOT NULL DEFAULT 'Manual';
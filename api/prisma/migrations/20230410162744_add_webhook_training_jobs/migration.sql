@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE `TrainingJob` ADD COLUMN `changes` JSON NULL,
+    MODIFY `trigger` ENUM('Manual', 'Scheduled', 'Webhook') NOT NULL DEFAULT 'Manual';
@@ -0,0 +1,8 @@
+-- AlterTable
+ALTER TABLE `Message` ADD COLUMN `citations` JSON NULL;
+
+-- Messages logged so far stored their citations as references
+UPDATE `Message` SET `citations` = `references`, `references` = JSON_ARRAY();
+
+-- AlterTable
+ALTER TABLE `Message` MODIFY `citations` JSON NOT NULL;
@@ -0,0 +1,5 @@
+-- AlterTable
+ALTER TABLE `TrainingJob` ADD COLUMN `heartbeatAt` DATETIME(3) NULL;
+
+-- Jobs running before heartbeats were recorded are considered alive since they started
+UPDATE `TrainingJob` SET `heartbeatAt` = COALESCE(`startedAt`, `createdAt`) WHERE `status` = 'Running';
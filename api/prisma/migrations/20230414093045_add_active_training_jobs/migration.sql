@@ -0,0 +1,15 @@
+-- AlterTable
+ALTER TABLE `TrainingJob` ADD COLUMN `activeFor` VARCHAR(191) NULL,
+    ADD COLUMN `followUp` JSON NULL;
+
+-- Only the latest queued or running job of each project is kept active
+UPDATE `TrainingJob` t
+    JOIN (
+        SELECT `projectId`, MAX(`createdAt`) AS `createdAt` FROM `TrainingJob`
+        WHERE `status` IN ('Queued', 'Running') GROUP BY `projectId`
+    ) a ON t.`projectId` = a.`projectId` AND t.`createdAt` = a.`createdAt`
+    SET t.`activeFor` = t.`projectId`
+    WHERE t.`status` IN ('Queued', 'Running');
+
+-- CreateIndex
+CREATE UNIQUE INDEX `TrainingJob_activeFor_key` ON `TrainingJob`(`activeFor`);
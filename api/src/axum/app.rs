@@ -1,6 +1,7 @@
 use axum::Router;
 use sentry::integrations::tower::{NewSentryLayer, SentryHttpLayer};
 use std::env;
+use tokio::sync::mpsc;
 use tower_http::{
     cors::{AllowCredentials, AllowHeaders, AllowMethods, AllowOrigin, CorsLayer},
     request_id::{PropagateRequestIdLayer, SetRequestIdLayer},
@@ -13,7 +14,7 @@ use crate::{
         state,
     },
     http::routes,
-    utils::db,
+    utils::{db, scheduler, training},
 };
 
 const REQUIRED_ENV_VARS: &[&str] = &[
@@ -22,7 +23,6 @@ const REQUIRED_ENV_VARS: &[&str] = &[
     "INFLUX_DB",
     "MAIL_FROM",
     "INFLUX_ORG",
-    "QDRANT_URL",
     "INFLUX_HOST",
     "INFLUX_TOKEN",
     "DATABASE_URL",
@@ -40,6 +40,11 @@ pub async fn create() -> Router {
         .await
         .expect("Failed to migrate database");
 
+    let (queue, jobs) = mpsc::unbounded_channel();
+    let state = state::create(prisma, queue).await;
+    training::spawn_worker(state.clone(), jobs);
+    scheduler::spawn_scheduler(state.clone());
+
     Router::new()
         .merge(routes::mount())
         .layer(session::layer())
@@ -63,5 +68,5 @@ pub async fn create() -> Router {
         .layer(SetRequestIdLayer::x_request_id(RequestIdMaker::default()))
         .layer(SentryHttpLayer::with_transaction())
         .layer(NewSentryLayer::new_from_top())
-        .with_state(state::create(prisma).await)
+        .with_state(state)
 }
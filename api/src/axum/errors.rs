@@ -10,6 +10,22 @@ pub enum ApiError {
     #[status(StatusCode::NOT_FOUND)]
     ProjectNotFound,
 
+    #[error("Conversation not found.")]
+    #[status(StatusCode::NOT_FOUND)]
+    ConversationNotFound,
+
+    #[error("Answer not found.")]
+    #[status(StatusCode::NOT_FOUND)]
+    AnswerNotFound,
+
+    #[error("Source not found.")]
+    #[status(StatusCode::NOT_FOUND)]
+    SourceNotFound,
+
+    #[error("This project is already being trained.")]
+    #[status(StatusCode::CONFLICT)]
+    TrainingInProgress,
+
     #[error("Unauthorized.")]
     #[status(StatusCode::UNAUTHORIZED)]
     AuthenticationRequired,
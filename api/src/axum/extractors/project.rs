@@ -118,7 +118,8 @@ impl FromRequestParts<AppState> for ProjectFromOrigin {
             .prisma
             .project()
             .find_first(vec![
-                project::status::equals(prisma::ProjectStatus::Trained),
+                // Projects being re-trained, or whose last training failed, are still answered from their current index.
+                project::index_name::not(None),
                 project::WhereParam::Origins(prisma::read_filters::JsonFilter::ArrayContains(
                     Some(origin.into()),
                 )),
@@ -1,27 +1,70 @@
 use influxdb2::Client as InfluxDB;
 use pika::pika::{InitOptions, Pika, PrefixRecord};
-use std::{env, sync::Arc};
+use std::{env, sync::Arc, time::Duration};
 
-use crate::prisma::PrismaClient;
+use crate::{prisma::PrismaClient, utils::training::TrainingQueue};
+use ::clippy::{
+    cache::{self, AnswerCache},
+    store, CachedEmbedder, ChatModel, LexicalReranker, LlmReranker, OpenAI, Reranker, Retriever,
+};
 
 #[derive(Debug)]
 pub struct State {
     pub pika: Pika,
     pub influx: InfluxDB,
     pub prisma: PrismaClient,
+    pub chat: Arc<dyn ChatModel>,
+    pub retriever: Arc<Retriever>,
+    pub answers: Arc<AnswerCache>,
+    pub training: TrainingQueue,
 }
 
 #[allow(clippy::module_name_repetitions)]
 pub type AppState = Arc<State>;
 
-pub async fn create(prisma: PrismaClient) -> AppState {
+pub async fn create(prisma: PrismaClient, training: TrainingQueue) -> AppState {
+    let chat: Arc<dyn ChatModel> = Arc::new(OpenAI::new());
+
     Arc::new(State {
         prisma,
         pika: get_pika(),
         influx: get_influx().await,
+        retriever: Arc::new(get_retriever(chat.clone())),
+        answers: Arc::new(get_answer_cache()),
+        training,
+        chat,
     })
 }
 
+fn get_retriever(chat: Arc<dyn ChatModel>) -> Retriever {
+    let retriever = Retriever::new(
+        Arc::new(CachedEmbedder::new(Arc::new(OpenAI::new()))),
+        store::from_env().expect("Failed to initialize vector store."),
+    );
+
+    // Reranking is opt-in, as the retrieval scores are already a good ordering for most projects.
+    let reranker: Arc<dyn Reranker> = match env::var("RERANKER").as_deref() {
+        Ok("lexical") => Arc::new(LexicalReranker::default()),
+        Ok("llm") => Arc::new(LlmReranker::new(chat)),
+        _ => return retriever,
+    };
+
+    retriever.with_reranker(reranker)
+}
+
+fn get_answer_cache() -> AnswerCache {
+    let threshold = env::var("ANSWER_CACHE_THRESHOLD")
+        .ok()
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(cache::DEFAULT_THRESHOLD);
+    let ttl = env::var("ANSWER_CACHE_TTL")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .map_or(cache::DEFAULT_TTL, Duration::from_secs);
+
+    AnswerCache::new(threshold, ttl)
+}
+
 fn get_pika() -> Pika {
     let prefixes = vec![
         PrefixRecord {
@@ -39,6 +82,31 @@ fn get_pika() -> Pika {
             description: Some("Project ID".to_string()),
             secure: false,
         },
+        PrefixRecord {
+            prefix: "conv".to_string(),
+            description: Some("Conversation ID".to_string()),
+            secure: false,
+        },
+        PrefixRecord {
+            prefix: "msg".to_string(),
+            description: Some("Message ID".to_string()),
+            secure: false,
+        },
+        PrefixRecord {
+            prefix: "fdbk".to_string(),
+            description: Some("Feedback ID".to_string()),
+            secure: false,
+        },
+        PrefixRecord {
+            prefix: "train".to_string(),
+            description: Some("Training job ID".to_string()),
+            secure: false,
+        },
+        PrefixRecord {
+            prefix: "src".to_string(),
+            description: Some("Source ID".to_string()),
+            secure: false,
+        },
     ];
 
     Pika::new(prefixes, &InitOptions::default())
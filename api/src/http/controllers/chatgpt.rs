@@ -13,11 +13,11 @@ use crate::{
         errors::{ApiError, ApiResult},
         state::AppState,
     },
-    http::controllers::widget::AskRequest,
+    http::controllers::widget::{search_options, AskRequest},
     prisma::project,
     utils::influx,
 };
-use ::clippy::{search_project, Payload};
+use ::clippy::Payload;
 
 #[derive(serde::Serialize, OpgModel)]
 pub struct SearchResponse {
@@ -28,7 +28,7 @@ pub struct SearchResponse {
 pub async fn search(
     Path(name): Path<String>,
     State(state): State<AppState>,
-    Json(AskRequest { query }): Json<AskRequest>,
+    Json(AskRequest { query, filter }): Json<AskRequest>,
 ) -> ApiResult<Json<SearchResponse>> {
     let params = name
         .to_lowercase()
@@ -57,15 +57,18 @@ pub async fn search(
         .await
         .context("Failed to track widget search.")?;
 
-    let results = search_project(
-        &project
-            .index_name
-            .expect("Trained models should have an index set."),
-        &query,
-        5,
-    )
-    .await
-    .context("Failed to search project.")?;
+    let results = state
+        .retriever
+        .search_project(
+            &project
+                .index_name
+                .clone()
+                .expect("Trained models should have an index set."),
+            &query,
+            &search_options(&project, 5, filter),
+        )
+        .await
+        .context("Failed to search project.")?;
 
     let origins: Vec<String> =
         serde_json::from_value(project.origins).context("Failed to parse origins as JSON.")?;
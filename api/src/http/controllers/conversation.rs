@@ -0,0 +1,70 @@
+use anyhow::Context;
+use axum::extract::{Path, Query, State};
+use axum_jsonschema::Json;
+use prisma_client_rust::Direction;
+
+use crate::{
+    axum::{
+        errors::{ApiError, ApiResult},
+        extractors::Project,
+        state::AppState,
+    },
+    prisma::{conversation, message},
+};
+
+const PER_PAGE: i64 = 25;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Pagination {
+    #[serde(default)]
+    page: i64,
+}
+
+// List the widget conversations of a project, newest first
+pub async fn index(
+    Project(project): Project,
+    State(state): State<AppState>,
+    Query(Pagination { page }): Query<Pagination>,
+) -> ApiResult<Json<Vec<conversation::Data>>> {
+    let conversations = state
+        .prisma
+        .conversation()
+        .find_many(vec![conversation::project_id::equals(project.id)])
+        .order_by(conversation::created_at::order(Direction::Desc))
+        .skip(page.max(0).saturating_mul(PER_PAGE))
+        .take(PER_PAGE)
+        .with(
+            conversation::messages::fetch(vec![])
+                .order_by(message::created_at::order(Direction::Asc)),
+        )
+        .exec()
+        .await
+        .context("Failed to get conversations.")?;
+
+    Ok(Json(conversations))
+}
+
+// Get a conversation, with every question asked and the answers given
+pub async fn show(
+    Project(project): Project,
+    State(state): State<AppState>,
+    Path((_, conversation_id)): Path<(String, String)>,
+) -> ApiResult<Json<conversation::Data>> {
+    let conversation = state
+        .prisma
+        .conversation()
+        .find_first(vec![
+            conversation::id::equals(conversation_id),
+            conversation::project_id::equals(project.id),
+        ])
+        .with(
+            conversation::messages::fetch(vec![])
+                .order_by(message::created_at::order(Direction::Asc)),
+        )
+        .exec()
+        .await
+        .context("Failed to get conversation.")?
+        .ok_or(ApiError::ConversationNotFound)?;
+
+    Ok(Json(conversation))
+}
@@ -0,0 +1,159 @@
+use anyhow::Context;
+use axum::extract::{Query, State};
+use axum_jsonschema::Json;
+use prisma_client_rust::Direction;
+use schemars::JsonSchema;
+use validator::Validate;
+
+use crate::{
+    axum::{
+        errors::{ApiError, ApiResult},
+        extractors::{Project, ProjectFromOrigin},
+        state::AppState,
+    },
+    prisma::{conversation, feedback, message, Rating},
+};
+
+const PER_PAGE: i64 = 25;
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Vote {
+    Up,
+    Down,
+}
+
+impl From<Vote> for Rating {
+    fn from(vote: Vote) -> Self {
+        match vote {
+            Vote::Up => Self::Positive,
+            Vote::Down => Self::Negative,
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, Validate, JsonSchema)]
+pub struct Request {
+    /// The id of the answer, as sent in the `answer` event of the stream.
+    answer_id: String,
+    rating: Vote,
+    #[validate(length(max = 2000))]
+    comment: Option<String>,
+}
+
+// Rate an answer given by the widget. Rating it again replaces the previous rating.
+pub async fn store(
+    State(state): State<AppState>,
+    ProjectFromOrigin(project): ProjectFromOrigin,
+    Json(req): Json<Request>,
+) -> ApiResult<()> {
+    let answer = state
+        .prisma
+        .message()
+        .find_first(vec![
+            message::id::equals(req.answer_id),
+            message::conversation::is(vec![conversation::project_id::equals(project.id)]),
+        ])
+        .exec()
+        .await
+        .context("Failed to get answer.")?
+        .ok_or(ApiError::AnswerNotFound)?;
+
+    let id = state
+        .pika
+        .clone()
+        .gen("fdbk")
+        .context("Failed to generate feedback id.")?;
+
+    // Answers have at most one rating, so rating one again replaces it, even when both requests race.
+    state
+        .prisma
+        .feedback()
+        .upsert(
+            feedback::message_id::equals(answer.id.clone()),
+            feedback::create(
+                id,
+                req.rating.into(),
+                message::id::equals(answer.id),
+                vec![feedback::comment::set(req.comment.clone())],
+            ),
+            vec![
+                feedback::rating::set(req.rating.into()),
+                feedback::comment::set(req.comment),
+            ],
+        )
+        .exec()
+        .await
+        .context("Failed to store feedback.")?;
+
+    Ok(())
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Filters {
+    rating: Option<Vote>,
+    #[serde(default)]
+    page: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Summary {
+    positive: i64,
+    negative: i64,
+    /// The latest feedback, along with the answers it rates.
+    feedback: Vec<feedback::Data>,
+}
+
+fn for_project(project_id: String) -> feedback::WhereParam {
+    feedback::message::is(vec![message::conversation::is(vec![
+        conversation::project_id::equals(project_id),
+    ])])
+}
+
+// Get the ratings of a project's answers, newest first
+pub async fn index(
+    Project(project): Project,
+    State(state): State<AppState>,
+    Query(filters): Query<Filters>,
+) -> ApiResult<Json<Summary>> {
+    let count = |rating: Rating| {
+        state
+            .prisma
+            .feedback()
+            .count(vec![
+                for_project(project.id.clone()),
+                feedback::rating::equals(rating),
+            ])
+            .exec()
+    };
+
+    let positive = count(Rating::Positive)
+        .await
+        .context("Failed to count feedback.")?;
+    let negative = count(Rating::Negative)
+        .await
+        .context("Failed to count feedback.")?;
+
+    let mut params = vec![for_project(project.id.clone())];
+    if let Some(rating) = filters.rating {
+        params.push(feedback::rating::equals(rating.into()));
+    }
+
+    let feedback = state
+        .prisma
+        .feedback()
+        .find_many(params)
+        .order_by(feedback::created_at::order(Direction::Desc))
+        .skip(filters.page.max(0).saturating_mul(PER_PAGE))
+        .take(PER_PAGE)
+        .with(feedback::message::fetch())
+        .exec()
+        .await
+        .context("Failed to get feedback.")?;
+
+    Ok(Json(Summary {
+        positive,
+        negative,
+        feedback,
+    }))
+}
@@ -1,13 +1,23 @@
 pub mod auth;
 pub mod chatgpt;
+pub mod conversation;
+pub mod feedback;
 pub mod project;
+pub mod source;
 pub mod team;
+pub mod training;
 pub mod user;
+pub mod webhook;
 pub mod widget;
 
 pub use auth as AuthController;
 pub use chatgpt as ChatGPTController;
+pub use conversation as ConversationController;
+pub use feedback as FeedbackController;
 pub use project as ProjectController;
+pub use source as SourceController;
 pub use team as TeamController;
+pub use training as TrainingController;
 pub use user as UserController;
+pub use webhook as WebhookController;
 pub use widget as WidgetController;
@@ -2,17 +2,24 @@ use anyhow::Context;
 use axum::extract::State;
 use axum_jsonschema::Json;
 use lazy_static::lazy_static;
+use prisma_client_rust::Direction;
 use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer};
 use serde_json::{json, Value};
+use std::fs;
+use validator::Validate;
 
 use crate::{
     axum::{
-        errors::ApiResult,
+        errors::{ApiError, ApiResult},
         extractors::{Project, TeamForUser},
         state::AppState,
     },
-    prisma::{project, team},
+    http::controllers::source::archive_path,
+    prisma::{project, source, team, training_job, SourceType},
+    utils::scheduler::next_refresh,
 };
+use ::clippy::{drop_index, PromptTemplate};
 
 lazy_static! {
     static ref DEFAULT_COPY: Value = json!({
@@ -24,17 +31,115 @@ lazy_static! {
     });
 }
 
-#[derive(Debug, serde::Deserialize, JsonSchema)]
+#[derive(Debug, serde::Deserialize, Validate, JsonSchema)]
 pub struct Request {
     name: String,
     origins: Vec<String>,
     image_url: Option<String>,
+    chat_model: Option<String>,
+    #[validate(range(min = 0.0, max = 2.0))]
+    temperature: Option<f64>,
+    /// Omit it to keep the current limit, or set it to `null` to remove it.
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(range(min = 1, max = 4096))]
+    max_tokens: Option<Option<i32>>,
+    /// How many tokens of documentation the prompt can include.
+    #[validate(range(min = 100, max = 16000))]
+    context_tokens: Option<i32>,
+    #[validate(range(min = 0.0, max = 1.0))]
+    keyword_weight: Option<f64>,
+    /// The system prompt, with `{{ sources }}`, `{{ question }}` and `{{ project }}` placeholders. Omit it to keep the current prompt, or set it to `null` to use the default prompt.
+    #[serde(default, deserialize_with = "nullable")]
+    prompt_template: Option<Option<String>>,
+    /// How many minutes to wait between automatic re-syncs of the project's sources. Omit it to keep the current schedule, or set it to `null` to only train the project manually.
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(range(min = 60, max = 43200))]
+    refresh_interval: Option<Option<i32>>,
 }
 
-#[allow(clippy::unused_async)]
-// Get details about a project
-pub async fn show(Project(project): Project) -> Json<project::Data> {
-    Json(project)
+/// Deserializes a field that can be omitted, to leave it as is, or set to `null`, to clear it.
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+impl Request {
+    fn chat_settings(&self) -> Vec<project::SetParam> {
+        let mut params = vec![];
+
+        if let Some(max_tokens) = self.max_tokens {
+            params.push(project::max_tokens::set(max_tokens));
+        }
+
+        if let Some(chat_model) = &self.chat_model {
+            params.push(project::chat_model::set(chat_model.clone()));
+        }
+
+        if let Some(temperature) = self.temperature {
+            params.push(project::temperature::set(temperature));
+        }
+
+        if let Some(context_tokens) = self.context_tokens {
+            params.push(project::context_tokens::set(context_tokens));
+        }
+
+        params
+    }
+
+    fn prompt_settings(&self) -> ApiResult<Vec<project::SetParam>> {
+        let Some(template) = &self.prompt_template else {
+            return Ok(vec![]);
+        };
+
+        if let Some(template) = template {
+            PromptTemplate::parse(template)
+                .map_err(|e| ApiError::ClientError(format!("Invalid prompt template: {e}")))?;
+        }
+
+        Ok(vec![project::prompt_template::set(template.clone())])
+    }
+
+    fn search_settings(&self) -> Vec<project::SetParam> {
+        self.keyword_weight
+            .map(|weight| vec![project::keyword_weight::set(weight)])
+            .unwrap_or_default()
+    }
+
+    /// Schedules the next refresh from now when the interval changes, so saving other settings doesn't push it back.
+    fn refresh_settings(&self, current: Option<i32>) -> Vec<project::SetParam> {
+        match self.refresh_interval {
+            Some(interval) if interval != current => vec![
+                project::refresh_interval::set(interval),
+                project::next_refresh_at::set(next_refresh(interval)),
+            ],
+            _ => vec![],
+        }
+    }
+}
+
+// Get details about a project, along with its latest training job
+pub async fn show(
+    Project(project): Project,
+    State(state): State<AppState>,
+) -> ApiResult<Json<project::Data>> {
+    let project = state
+        .prisma
+        .project()
+        .find_unique(project::id::equals(project.id))
+        .with(
+            project::training_jobs::fetch(vec![])
+                .order_by(training_job::created_at::order(Direction::Desc))
+                .take(1),
+        )
+        .exec()
+        .await
+        .context("Failed to get project.")?
+        .ok_or(ApiError::ProjectNotFound)?;
+
+    Ok(Json(project))
 }
 
 // Create a new project for the current team
@@ -49,6 +154,18 @@ pub async fn store(
         .gen("proj")
         .context("Failed to generate project id.")?;
 
+    let params = [
+        vec![
+            project::origins::set(req.origins.clone().into()),
+            project::image_url::set(req.image_url.clone()),
+        ],
+        req.chat_settings(),
+        req.prompt_settings()?,
+        req.search_settings(),
+        req.refresh_settings(None),
+    ]
+    .concat();
+
     let project = state
         .prisma
         .project()
@@ -57,10 +174,7 @@ pub async fn store(
             req.name,
             DEFAULT_COPY.clone(),
             team::id::equals(team.id),
-            vec![
-                project::origins::set(req.origins.into()),
-                project::image_url::set(req.image_url),
-            ],
+            params,
         )
         .exec()
         .await
@@ -74,17 +188,23 @@ pub async fn update(
     State(state): State<AppState>,
     Json(req): Json<Request>,
 ) -> ApiResult<Json<project::Data>> {
+    let params = [
+        vec![
+            project::name::set(req.name.clone()),
+            project::image_url::set(req.image_url.clone()),
+            project::origins::set(req.origins.clone().into()),
+        ],
+        req.chat_settings(),
+        req.prompt_settings()?,
+        req.search_settings(),
+        req.refresh_settings(project.refresh_interval),
+    ]
+    .concat();
+
     let updated_project = state
         .prisma
         .project()
-        .update(
-            project::id::equals(project.id),
-            vec![
-                project::name::set(req.name),
-                project::image_url::set(req.image_url),
-                project::origins::set(req.origins.into()),
-            ],
-        )
+        .update(project::id::equals(project.id), params)
         .exec()
         .await
         .context("Failed to update project.")?;
@@ -93,6 +213,41 @@ pub async fn update(
 }
 
 pub async fn delete(Project(project): Project, State(state): State<AppState>) -> ApiResult<()> {
+    // A running job would recreate the index we're about to drop.
+    let active_job = state
+        .prisma
+        .training_job()
+        .find_first(vec![training_job::active_for::equals(Some(
+            project.id.clone(),
+        ))])
+        .exec()
+        .await
+        .context("Failed to get the active training job.")?;
+
+    if active_job.is_some() {
+        return Err(ApiError::TrainingInProgress);
+    }
+
+    let archives = state
+        .prisma
+        .source()
+        .find_many(vec![
+            source::project_id::equals(project.id.clone()),
+            source::kind::equals(SourceType::Archive),
+        ])
+        .exec()
+        .await
+        .context("Failed to get project sources.")?;
+
+    if let Some(index_name) = &project.index_name {
+        drop_index(state.retriever.store.as_ref(), index_name)
+            .await
+            .context("Failed to delete project index.")?;
+
+        state.answers.invalidate(index_name);
+        state.retriever.keywords().remove(index_name);
+    }
+
     state
         .prisma
         .project()
@@ -101,5 +256,20 @@ pub async fn delete(Project(project): Project, State(state): State<AppState>) ->
         .await
         .context("Failed to delete project.")?;
 
+    for archive in archives {
+        fs::remove_file(archive_path(&archive.id)).ok();
+    }
+
     Ok(())
 }
+
+#[allow(clippy::unused_async)]
+// Forget the cached answers and keyword index of a project, so re-indexed documentation is used right away
+pub async fn flush_cache(Project(project): Project, State(state): State<AppState>) {
+    let Some(index_name) = project.index_name else {
+        return;
+    };
+
+    state.answers.invalidate(&index_name);
+    state.retriever.keywords().invalidate(&index_name);
+}
@@ -0,0 +1,295 @@
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+};
+use axum_jsonschema::Json;
+use prisma_client_rust::Direction;
+use schemars::JsonSchema;
+use std::{env, fs, path::PathBuf};
+use url::{Host, Url};
+
+use crate::{
+    axum::{
+        errors::{ApiError, ApiResult},
+        extractors::Project,
+        state::AppState,
+    },
+    prisma::{project, source, SourceType},
+};
+use ::clippy::train::{is_public, Source, SourceKind};
+
+/// The largest archive that can be uploaded, in bytes.
+pub const MAX_ARCHIVE_SIZE: usize = 50 * 1024 * 1024;
+/// The most pages a website source can crawl.
+pub const MAX_PAGES: usize = 1000;
+
+#[derive(Debug, serde::Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Kind {
+    /// The markdown files of a GitHub repository, like `owner/repo`.
+    GitHub {
+        repo: String,
+        /// Defaults to `main`.
+        branch: Option<String>,
+        /// The directory of the repository the documentation lives in, like `docs`.
+        path: Option<String>,
+    },
+    /// The pages of a website, crawled from a base URL.
+    Website {
+        url: String,
+        /// The number of pages to crawl at most, up to 1000.
+        max_pages: Option<usize>,
+    },
+    /// A zip archive of markdown files, uploaded to `/project/:project/sources/:source/archive` once the source is created.
+    Archive,
+}
+
+#[derive(Debug, serde::Deserialize, JsonSchema)]
+pub struct Request {
+    #[serde(flatten)]
+    kind: Kind,
+    /// Globs the path of a file has to match one of to be trained on, like `guides/**`.
+    #[serde(default)]
+    include: Vec<String>,
+    /// Globs the path of a file can't match any of to be trained on, like `**/changelog.md`.
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+impl Request {
+    fn into_source(self, source_id: &str) -> ApiResult<(SourceType, Source)> {
+        let (source_type, kind) = match self.kind {
+            Kind::GitHub { repo, branch, path } => {
+                let valid = repo.split_once('/').map_or(false, |(owner, name)| {
+                    !owner.is_empty() && !name.is_empty() && !name.contains('/')
+                });
+
+                if !valid {
+                    return Err(ApiError::ClientError(
+                        "Repositories should look like `owner/repo`.".to_string(),
+                    ));
+                }
+
+                (
+                    SourceType::GitHub,
+                    SourceKind::GitHub {
+                        repo,
+                        path,
+                        branch: branch.unwrap_or_else(|| "main".to_string()),
+                    },
+                )
+            }
+            Kind::Website { url, max_pages } => {
+                check_website(&url)?;
+
+                (
+                    SourceType::Website,
+                    SourceKind::Website {
+                        url,
+                        max_pages: Some(max_pages.map_or(MAX_PAGES, |max| max.min(MAX_PAGES))),
+                    },
+                )
+            }
+            Kind::Archive => (
+                SourceType::Archive,
+                SourceKind::Archive {
+                    path: archive_path(source_id),
+                },
+            ),
+        };
+
+        let source = Source {
+            kind,
+            include: self.include,
+            exclude: self.exclude,
+        };
+        source
+            .validate()
+            .map_err(|e| ApiError::ClientError(e.to_string()))?;
+
+        Ok((source_type, source))
+    }
+}
+
+/// Makes sure a website is served over HTTP and its host isn't an internal one. Hosts are checked again once resolved, when the website is crawled.
+fn check_website(url: &str) -> ApiResult<()> {
+    let url = Url::parse(url).map_err(|e| ApiError::ClientError(format!("Invalid URL: {e}")))?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ApiError::ClientError(
+            "Websites should be served over http or https.".to_string(),
+        ));
+    }
+
+    let public = match url.host() {
+        Some(Host::Domain(domain)) => domain != "localhost" && !domain.ends_with(".localhost"),
+        Some(Host::Ipv4(ip)) => is_public(ip.into()),
+        Some(Host::Ipv6(ip)) => is_public(ip.into()),
+        None => false,
+    };
+
+    if !public {
+        return Err(ApiError::ClientError(
+            "Websites should be publicly reachable.".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Where the archive uploaded for a source is stored.
+pub(crate) fn archive_path(source_id: &str) -> PathBuf {
+    PathBuf::from(env::var("ARCHIVE_PATH").unwrap_or_else(|_| "storage/archives".to_string()))
+        .join(format!("{source_id}.zip"))
+}
+
+async fn find(state: &AppState, project_id: String, source_id: String) -> ApiResult<source::Data> {
+    state
+        .prisma
+        .source()
+        .find_first(vec![
+            source::id::equals(source_id),
+            source::project_id::equals(project_id),
+        ])
+        .exec()
+        .await
+        .context("Failed to get source.")?
+        .ok_or(ApiError::SourceNotFound)
+}
+
+// Get the sources a project is trained on
+pub async fn index(
+    Project(project): Project,
+    State(state): State<AppState>,
+) -> ApiResult<Json<Vec<source::Data>>> {
+    let sources = state
+        .prisma
+        .source()
+        .find_many(vec![source::project_id::equals(project.id)])
+        .order_by(source::created_at::order(Direction::Asc))
+        .exec()
+        .await
+        .context("Failed to get sources.")?;
+
+    Ok(Json(sources))
+}
+
+// Add a source to train a project on
+pub async fn store(
+    Project(project): Project,
+    State(state): State<AppState>,
+    Json(req): Json<Request>,
+) -> ApiResult<Json<source::Data>> {
+    let id = state
+        .pika
+        .clone()
+        .gen("src")
+        .context("Failed to generate source id.")?;
+
+    let (source_type, config) = req.into_source(&id)?;
+
+    let source = state
+        .prisma
+        .source()
+        .create(
+            id,
+            source_type,
+            serde_json::to_value(config).context("Failed to serialize source.")?,
+            project::id::equals(project.id),
+            vec![],
+        )
+        .exec()
+        .await
+        .context("Failed to create source.")?;
+
+    Ok(Json(source))
+}
+
+pub async fn show(
+    Project(project): Project,
+    State(state): State<AppState>,
+    Path((_, source_id)): Path<(String, String)>,
+) -> ApiResult<Json<source::Data>> {
+    Ok(Json(find(&state, project.id, source_id).await?))
+}
+
+pub async fn update(
+    Project(project): Project,
+    State(state): State<AppState>,
+    Path((_, source_id)): Path<(String, String)>,
+    Json(req): Json<Request>,
+) -> ApiResult<Json<source::Data>> {
+    let source = find(&state, project.id, source_id).await?;
+    let (source_type, config) = req.into_source(&source.id)?;
+
+    let source = state
+        .prisma
+        .source()
+        .update(
+            source::id::equals(source.id),
+            vec![
+                source::kind::set(source_type),
+                source::config::set(
+                    serde_json::to_value(config).context("Failed to serialize source.")?,
+                ),
+            ],
+        )
+        .exec()
+        .await
+        .context("Failed to update source.")?;
+
+    Ok(Json(source))
+}
+
+pub async fn delete(
+    Project(project): Project,
+    State(state): State<AppState>,
+    Path((_, source_id)): Path<(String, String)>,
+) -> ApiResult<()> {
+    let source = find(&state, project.id, source_id).await?;
+
+    state
+        .prisma
+        .source()
+        .delete(source::id::equals(source.id.clone()))
+        .exec()
+        .await
+        .context("Failed to delete source.")?;
+
+    fs::remove_file(archive_path(&source.id)).ok();
+
+    Ok(())
+}
+
+// Upload the zip archive of an archive source, replacing the previous one
+pub async fn upload(
+    Project(project): Project,
+    State(state): State<AppState>,
+    Path((_, source_id)): Path<(String, String)>,
+    archive: Bytes,
+) -> ApiResult<()> {
+    let source = find(&state, project.id, source_id).await?;
+
+    if source.kind != SourceType::Archive {
+        return Err(ApiError::ClientError(
+            "Only archive sources can have an archive uploaded.".to_string(),
+        ));
+    }
+
+    if !archive.starts_with(b"PK\x03\x04") {
+        return Err(ApiError::ClientError(
+            "Archives should be zip files.".to_string(),
+        ));
+    }
+
+    let path = archive_path(&source.id);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create archive directory.")?;
+    }
+    tokio::fs::write(&path, archive)
+        .await
+        .context("Failed to store archive.")?;
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use anyhow::Context;
+use axum::extract::{Query, State};
+use axum_jsonschema::Json;
+use prisma_client_rust::Direction;
+
+use crate::{
+    axum::{
+        errors::{ApiError, ApiResult},
+        extractors::Project,
+        state::AppState,
+    },
+    prisma::{training_job, TrainingTrigger},
+    utils::training::{self, Queued},
+};
+
+const PER_PAGE: i64 = 25;
+
+// Queue a job that fetches, parses and embeds the documentation of the project's sources
+pub async fn store(
+    Project(project): Project,
+    State(state): State<AppState>,
+) -> ApiResult<Json<training_job::Data>> {
+    match training::queue(&state, &project.id, TrainingTrigger::Manual).await? {
+        Queued::Job(job) => Ok(Json(job)),
+        Queued::InProgress => Err(ApiError::TrainingInProgress),
+        Queued::NoSources => Err(ApiError::ClientError(
+            "Add a source before training the project.".to_string(),
+        )),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Pagination {
+    #[serde(default)]
+    page: i64,
+}
+
+// Get the training jobs of a project, newest first
+pub async fn index(
+    Project(project): Project,
+    State(state): State<AppState>,
+    Query(Pagination { page }): Query<Pagination>,
+) -> ApiResult<Json<Vec<training_job::Data>>> {
+    let jobs = state
+        .prisma
+        .training_job()
+        .find_many(vec![training_job::project_id::equals(project.id)])
+        .order_by(training_job::created_at::order(Direction::Desc))
+        .skip(page.max(0).saturating_mul(PER_PAGE))
+        .take(PER_PAGE)
+        .exec()
+        .await
+        .context("Failed to get training jobs.")?;
+
+    Ok(Json(jobs))
+}
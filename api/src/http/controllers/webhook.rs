@@ -0,0 +1,187 @@
+use anyhow::Context;
+use axum::{body::Bytes, extract::State, http::HeaderMap};
+use axum_jsonschema::Json;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    path::Path,
+};
+use tracing::info;
+
+use crate::{
+    axum::{
+        errors::{ApiError, ApiResult},
+        state::AppState,
+    },
+    prisma::{project, source, training_job, SourceType},
+    utils::{crypto::verify_hmac_sha256, training},
+};
+use ::clippy::train::{is_supported, Source, SourceKind};
+
+/// GitHub only lists this many commits in a push payload.
+const MAX_COMMITS: usize = 20;
+/// The commit GitHub reports on the missing side of a push that creates or deletes a branch.
+const NULL_COMMIT: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug, serde::Deserialize)]
+struct Push {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    before: String,
+    after: String,
+    #[serde(default)]
+    forced: bool,
+    repository: Repository,
+    #[serde(default)]
+    commits: Vec<Commit>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Repository {
+    full_name: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Commit {
+    id: String,
+    #[serde(default)]
+    added: Vec<String>,
+    #[serde(default)]
+    removed: Vec<String>,
+    #[serde(default)]
+    modified: Vec<String>,
+}
+
+impl Push {
+    /// The documentation files the pushed commits added, removed or modified, relative to the repository, or `None` if the payload doesn't list every commit between `before` and `after`.
+    ///
+    /// That's the case for pushes of more commits than GitHub lists, new branches and force pushes, whose files can only be found by fetching the whole repository again.
+    fn changed_files(&self) -> Option<Vec<String>> {
+        let complete = self.commits.len() < MAX_COMMITS
+            && self.before != NULL_COMMIT
+            && !self.forced
+            && self.commits.last().map(|commit| commit.id.as_str()) == Some(self.after.as_str());
+        if !complete {
+            return None;
+        }
+
+        let changes = self
+            .commits
+            .iter()
+            .flat_map(|commit| {
+                commit
+                    .added
+                    .iter()
+                    .chain(&commit.removed)
+                    .chain(&commit.modified)
+            })
+            .filter(|path| is_supported(Path::new(path)))
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        Some(changes)
+    }
+}
+
+/// Whether a source trains on the given branch of a repository.
+fn tracks(source: &source::Data, repo: &str, branch: &str) -> bool {
+    match serde_json::from_value::<Source>(source.config.clone()).map(|source| source.kind) {
+        Ok(SourceKind::GitHub {
+            repo: tracked_repo,
+            branch: tracked_branch,
+            ..
+        }) => tracked_repo.eq_ignore_ascii_case(repo) && tracked_branch == branch,
+        _ => false,
+    }
+}
+
+// Re-index the documentation a push changed in the projects trained on the repository
+pub async fn github(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult<Json<Vec<training_job::Data>>> {
+    let secret = env::var("GITHUB_WEBHOOK_SECRET").context("GITHUB_WEBHOOK_SECRET not set")?;
+    let signature = headers
+        .get("X-Hub-Signature-256")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("sha256="))
+        .ok_or(ApiError::InvalidSignature)?;
+
+    if !verify_hmac_sha256(&secret, &body, signature) {
+        return Err(ApiError::InvalidSignature);
+    }
+
+    // GitHub sends a ping when the webhook is created, which doesn't need anything but a successful response.
+    if headers
+        .get("X-GitHub-Event")
+        .map_or(true, |event| event != "push")
+    {
+        return Ok(Json(vec![]));
+    }
+
+    let push: Push = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::ClientError(format!("Invalid push payload: {e}")))?;
+
+    // Pushed tags don't change the documentation of any branch, and deleted branches have none left to index.
+    let Some(branch) = push.git_ref.strip_prefix("refs/heads/") else {
+        return Ok(Json(vec![]));
+    };
+    if push.after == NULL_COMMIT {
+        return Ok(Json(vec![]));
+    }
+
+    let changes = push.changed_files();
+    if changes.as_ref().map_or(false, Vec::is_empty) {
+        return Ok(Json(vec![]));
+    }
+
+    let mut sources = BTreeMap::<String, Vec<source::Data>>::new();
+    for source in state
+        .prisma
+        .source()
+        .find_many(vec![source::kind::equals(SourceType::GitHub)])
+        .exec()
+        .await
+        .context("Failed to get sources.")?
+    {
+        if tracks(&source, &push.repository.full_name, branch) {
+            sources
+                .entry(source.project_id.clone())
+                .or_default()
+                .push(source);
+        }
+    }
+
+    let projects = state
+        .prisma
+        .project()
+        .find_many(vec![project::id::in_vec(sources.keys().cloned().collect())])
+        .exec()
+        .await
+        .context("Failed to get projects.")?;
+
+    let mut jobs = Vec::with_capacity(projects.len());
+    for project in projects {
+        let sources = sources
+            .remove(&project.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|source| source.config)
+            .collect();
+
+        if let Some(job) =
+            training::queue_changes(&state, &project, sources, changes.clone()).await?
+        {
+            info!(
+                "Queued training job {} to update {} from a push to {}.",
+                job.id, project.id, push.repository.full_name
+            );
+            jobs.push(job);
+        }
+    }
+
+    Ok(Json(jobs))
+}
@@ -7,18 +7,29 @@ use axum::{
     },
 };
 use axum_jsonschema::Json;
-use futures::Stream;
+use futures::{stream, Stream};
+use prisma_client_rust::Direction;
 use schemars::JsonSchema;
 use serde_json::Value;
-use std::convert::Infallible;
+use std::{collections::HashMap, convert::Infallible};
 use tokio_stream::StreamExt;
 
 use crate::{
-    axum::{errors::ApiResult, extractors::ProjectFromOrigin, state::AppState},
-    prisma::project,
-    utils::influx,
+    axum::{
+        errors::{ApiError, ApiResult},
+        extractors::ProjectFromOrigin,
+        state::AppState,
+    },
+    prisma::{conversation, message, project},
+    utils::{self, conversation::PendingAnswer, influx},
+};
+use ::clippy::{
+    budget::ContextBudget,
+    chat::{ChatMessage, Role},
+    store::Filter,
+    stream::{PartialResult, Question},
+    ChatSettings, Payload, PromptTemplate, SearchOptions,
 };
-use ::clippy::{search_project, stream::PartialResult, Payload};
 
 #[derive(Debug, serde::Serialize)]
 pub struct PartialProject {
@@ -54,26 +65,91 @@ pub async fn show(
 pub struct AskRequest {
     #[opg("The query to search for")]
     pub query: String,
+    #[opg("Restricts the search to pages matching every condition")]
+    pub filter: Option<SearchFilter>,
+}
+
+#[derive(Debug, serde::Deserialize, JsonSchema, opg::OpgModel)]
+pub struct SearchFilter {
+    #[opg("Only search pages under this path, like /api/")]
+    pub path_prefix: Option<String>,
+    #[opg("Only search pages with this title")]
+    pub page_title: Option<String>,
+    #[serde(default)]
+    #[opg("Metadata values the pages must have, like a version or language")]
+    pub metadata: HashMap<String, String>,
+    #[serde(default)]
+    #[opg("Tags the pages must all have")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, JsonSchema)]
+pub struct StreamRequest {
+    pub query: String,
+    pub filter: Option<SearchFilter>,
+    /// The conversation to continue. Its stored messages take the place of `history`.
+    pub conversation_id: Option<String>,
+    /// The previous turns of the conversation, oldest first.
+    #[serde(default)]
+    pub history: Vec<Turn>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Speaker {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, serde::Deserialize, JsonSchema)]
+pub struct Turn {
+    pub role: Speaker,
+    pub content: String,
+}
+
+impl From<Turn> for ChatMessage {
+    fn from(turn: Turn) -> Self {
+        let role = match turn.role {
+            Speaker::User => Role::User,
+            Speaker::Assistant => Role::Assistant,
+        };
+
+        Self::new(role, turn.content)
+    }
+}
+
+impl From<SearchFilter> for Filter {
+    fn from(filter: SearchFilter) -> Self {
+        Self {
+            path_prefix: filter.path_prefix,
+            page_title: filter.page_title,
+            metadata: filter.metadata,
+            tags: filter.tags,
+        }
+    }
 }
 
 pub async fn search(
     State(state): State<AppState>,
     ProjectFromOrigin(project): ProjectFromOrigin,
-    Json(AskRequest { query }): Json<AskRequest>,
+    Json(AskRequest { query, filter }): Json<AskRequest>,
 ) -> ApiResult<Json<Vec<Payload>>> {
     influx::track_search(&state.influx, &project.id)
         .await
         .context("Failed to track widget search.")?;
 
-    let results = search_project(
-        &project
-            .index_name
-            .expect("Trained models should have an index set."),
-        &query,
-        5,
-    )
-    .await
-    .context("Failed to search project.")?;
+    let results = state
+        .retriever
+        .search_project(
+            &project
+                .index_name
+                .clone()
+                .expect("Trained models should have an index set."),
+            &query,
+            &search_options(&project, 5, filter),
+        )
+        .await
+        .context("Failed to search project.")?;
 
     Ok(Json(
         results.into_iter().map(|r| r.payload).collect::<Vec<_>>(),
@@ -85,35 +161,172 @@ pub struct StreamError {
     pub error: &'static str,
 }
 
-#[allow(clippy::unused_async)]
 pub async fn stream(
     State(state): State<AppState>,
     ProjectFromOrigin(project): ProjectFromOrigin,
-    Json(AskRequest { query }): Json<AskRequest>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    Json(StreamRequest {
+        query,
+        filter,
+        history,
+        conversation_id,
+    }): Json<StreamRequest>,
+) -> ApiResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
     influx::track_query(&state.influx, &project.id)
         .await
-        .unwrap();
+        .context("Failed to track widget query.")?;
 
-    let stream = clippy::stream::ask(
-        project
+    let (conversation, history) = match conversation_id {
+        Some(id) => {
+            let conversation = state
+                .prisma
+                .conversation()
+                .find_first(vec![
+                    conversation::id::equals(id),
+                    conversation::project_id::equals(project.id.clone()),
+                ])
+                .with(
+                    conversation::messages::fetch(vec![])
+                        .order_by(message::created_at::order(Direction::Asc)),
+                )
+                .exec()
+                .await
+                .context("Failed to load conversation.")?
+                .ok_or(ApiError::ConversationNotFound)?;
+
+            let history =
+                utils::conversation::history(conversation.messages.as_deref().unwrap_or_default());
+
+            (conversation, history)
+        }
+        None => {
+            let id = state
+                .pika
+                .clone()
+                .gen("conv")
+                .context("Failed to generate conversation id.")?;
+
+            let conversation = state
+                .prisma
+                .conversation()
+                .create(id, project::id::equals(project.id.clone()), vec![])
+                .exec()
+                .await
+                .context("Failed to create conversation.")?;
+
+            (conversation, history.into_iter().map(Into::into).collect())
+        }
+    };
+
+    let settings = chat_settings(&project);
+    let model = settings.model.clone();
+    // Every training updates the index and `trainedAt`, so answers are only replayed until the project is trained again.
+    let generation = project
+        .trained_at
+        .map(|trained_at| trained_at.to_rfc3339())
+        .unwrap_or_default();
+    let question = Question {
+        history,
+        settings,
+        query: query.clone(),
+        prompt: prompt_template(&project),
+        budget: ContextBudget {
+            max_tokens: usize::try_from(project.context_tokens).unwrap_or_default(),
+            ..ContextBudget::default()
+        },
+        project_name: project.name.clone(),
+        search: search_options(&project, 3, filter),
+        project_id: project
             .index_name
             .expect("Trained models should have an index set."),
+    };
+
+    let answer = PendingAnswer {
         query,
+        model,
+        id: state
+            .pika
+            .clone()
+            .gen("msg")
+            .context("Failed to generate answer id.")?,
+        conversation_id: conversation.id.clone(),
+    };
+    let answer_id = answer.id.clone();
+
+    let stream = clippy::stream::ask(
+        state.retriever.clone(),
+        state.chat.clone(),
+        Some((state.answers.clone(), generation)),
+        question,
     );
+    let stream = utils::conversation::log_answer(state.clone(), answer, stream)
+        .await
+        .context("Failed to log answer.")?;
+
+    Ok(into_sse(conversation.id, answer_id, stream))
+}
+
+fn chat_settings(project: &project::Data) -> ChatSettings {
+    #[allow(clippy::cast_possible_truncation)]
+    ChatSettings {
+        model: project.chat_model.clone(),
+        temperature: project.temperature as f32,
+        max_tokens: project.max_tokens.and_then(|t| u16::try_from(t).ok()),
+    }
+}
+
+fn prompt_template(project: &project::Data) -> PromptTemplate {
+    // Templates are validated when saved, so only projects without one get the default.
+    project
+        .prompt_template
+        .as_deref()
+        .and_then(|template| PromptTemplate::parse(template).ok())
+        .unwrap_or_default()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+pub fn search_options(
+    project: &project::Data,
+    count: usize,
+    filter: Option<SearchFilter>,
+) -> SearchOptions {
+    SearchOptions {
+        count,
+        keyword_weight: project.keyword_weight as f32,
+        filter: filter.map(Into::into).unwrap_or_default(),
+        ..SearchOptions::default()
+    }
+}
+
+fn into_sse(
+    conversation_id: String,
+    answer_id: String,
+    stream: impl Stream<Item = anyhow::Result<PartialResult>> + Send + 'static,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let ids = stream::iter([
+        Ok::<_, Infallible>(Event::default().id("conversation").data(conversation_id)),
+        Ok::<_, Infallible>(Event::default().id("answer").data(answer_id)),
+    ]);
 
     let stream = stream.map(|e| {
         let Ok(event) = e else {
-            return Ok::<_, Infallible>(Event::default().id("error").json_data(StreamError {
-                error: "Failed to complete query."
-            }).unwrap())
+            return Ok::<_, Infallible>(
+                Event::default()
+                    .id("error")
+                    .json_data(StreamError {
+                        error: "Failed to complete query.",
+                    })
+                    .unwrap(),
+            );
         };
 
         match event {
-            PartialResult::References(results) => Ok::<_, Infallible>(
+            PartialResult::Context(report) => {
+                Ok::<_, Infallible>(Event::default().id("context").json_data(report).unwrap())
+            }
+            PartialResult::Citations(citations) => Ok::<_, Infallible>(
                 Event::default()
-                    .id("references")
-                    .json_data(results)
+                    .id("citations")
+                    .json_data(citations)
                     .unwrap(),
             ),
             PartialResult::PartialAnswer(answer) => {
@@ -130,5 +343,80 @@ pub async fn stream(
         }
     });
 
-    Sse::new(stream).keep_alive(KeepAlive::default())
+    Sse::new(ids.chain(stream)).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::HttpBody, response::IntoResponse};
+    use clippy::{
+        chat::ScriptedChatModel, store::PointStruct, Embedder, HashEmbedder, LocalStore, Retriever,
+        VectorStore,
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn streams_answer_as_server_sent_events() {
+        let embedder = Arc::new(HashEmbedder::new(64));
+        let store = Arc::new(LocalStore::default());
+        let chat = Arc::new(ScriptedChatModel::default().reply(["Hello", " world! [1]"]));
+
+        store.create_collection("docs", 64).await.unwrap();
+        store
+            .upsert(
+                "docs",
+                &[PointStruct {
+                    id: "1".to_string(),
+                    vector: embedder.raw_embed("Greetings").await.unwrap(),
+                    payload: Payload {
+                        path: "/greetings".to_string(),
+                        title: "Greetings".to_string(),
+                        page_title: "Getting Started".to_string(),
+                        text: "Say hello to the world.".to_string(),
+                        ..Payload::default()
+                    },
+                }],
+            )
+            .await
+            .unwrap();
+
+        let stream = clippy::stream::ask(
+            Arc::new(Retriever::new(embedder, store)),
+            chat,
+            None,
+            Question {
+                project_id: "docs".to_string(),
+                project_name: "Greeter".to_string(),
+                prompt: PromptTemplate::default(),
+                budget: ContextBudget::default(),
+                query: "How do I greet people?".to_string(),
+                history: Vec::new(),
+                settings: ChatSettings::default(),
+                search: SearchOptions::default(),
+            },
+        );
+
+        let mut body = into_sse("conv_1".to_string(), "msg_1".to_string(), stream)
+            .into_response()
+            .into_body();
+        let mut output = String::new();
+        while let Some(chunk) = body.data().await {
+            output.push_str(&String::from_utf8(chunk.unwrap().to_vec()).unwrap());
+        }
+
+        assert!(output.contains("id: conversation\n"));
+        assert!(output.contains("id: answer\n"));
+        assert!(output.find("data: conv_1\n").unwrap() < output.find("data: msg_1\n").unwrap());
+        assert!(output.find("data: msg_1\n").unwrap() < output.find("data: Hello\n").unwrap());
+        assert!(output.contains("id: context\n"));
+        assert!(output.contains("\"inclusion\":\"included\""));
+        assert!(output.contains("id: partial_answer\n"));
+        assert!(
+            output.find("data: Hello\n").unwrap() < output.find("data:  world! [1]\n").unwrap()
+        );
+        assert!(output.contains("id: citations\n"));
+        assert!(output.contains("\"text\":\"Say hello to the world.\""));
+        assert!(output.contains("\"spans\":[{\"start\":13,\"end\":16}]"));
+    }
 }
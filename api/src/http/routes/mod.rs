@@ -5,6 +5,7 @@ mod auth;
 mod chatgpt;
 mod project;
 mod team;
+mod webhook;
 mod widget;
 
 use crate::axum::state::AppState;
@@ -16,6 +17,7 @@ pub fn mount() -> Router<AppState> {
         .merge(project::mount())
         .merge(team::mount())
         .merge(chatgpt::mount())
+        .merge(webhook::mount())
         .route("/version", get(version))
         .route("/", get(|| async { Redirect::to("https://clippy.help") }))
 }
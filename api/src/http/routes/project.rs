@@ -1,9 +1,16 @@
 use axum::{
-    routing::{delete, get, post},
+    extract::DefaultBodyLimit,
+    routing::{delete, get, post, put},
     Router,
 };
 
-use crate::{axum::state::AppState, http::controllers::ProjectController};
+use crate::{
+    axum::state::AppState,
+    http::controllers::{
+        source::MAX_ARCHIVE_SIZE, ConversationController, FeedbackController, ProjectController,
+        SourceController, TrainingController,
+    },
+};
 
 pub fn mount() -> Router<AppState> {
     Router::new().nest(
@@ -11,6 +18,24 @@ pub fn mount() -> Router<AppState> {
         Router::new()
             .route("/", get(ProjectController::show))
             .route("/", post(ProjectController::update))
-            .route("/", delete(ProjectController::delete)),
+            .route("/", delete(ProjectController::delete))
+            .route("/cache", delete(ProjectController::flush_cache))
+            .route("/sources", get(SourceController::index))
+            .route("/sources", post(SourceController::store))
+            .route("/sources/:source", get(SourceController::show))
+            .route("/sources/:source", post(SourceController::update))
+            .route("/sources/:source", delete(SourceController::delete))
+            .route(
+                "/sources/:source/archive",
+                put(SourceController::upload).layer(DefaultBodyLimit::max(MAX_ARCHIVE_SIZE)),
+            )
+            .route("/train", get(TrainingController::index))
+            .route("/train", post(TrainingController::store))
+            .route("/conversations", get(ConversationController::index))
+            .route(
+                "/conversations/:conversation",
+                get(ConversationController::show),
+            )
+            .route("/feedback", get(FeedbackController::index)),
     )
 }
@@ -0,0 +1,10 @@
+use axum::{routing::post, Router};
+
+use crate::{axum::state::AppState, http::controllers::WebhookController};
+
+pub fn mount() -> Router<AppState> {
+    Router::new().nest(
+        "/webhooks",
+        Router::new().route("/github", post(WebhookController::github)),
+    )
+}
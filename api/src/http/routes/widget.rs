@@ -3,7 +3,10 @@ use axum::{
     Router,
 };
 
-use crate::{axum::state::AppState, http::controllers::WidgetController};
+use crate::{
+    axum::state::AppState,
+    http::controllers::{FeedbackController, WidgetController},
+};
 
 pub fn mount() -> Router<AppState> {
     Router::new().nest(
@@ -11,6 +14,7 @@ pub fn mount() -> Router<AppState> {
         Router::new()
             .route("/", get(WidgetController::show))
             .route("/search", post(WidgetController::search))
-            .route("/stream", post(WidgetController::stream)),
+            .route("/stream", post(WidgetController::stream))
+            .route("/feedback", post(FeedbackController::store)),
     )
 }
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use futures::{Stream, StreamExt};
+use std::{
+    mem,
+    time::{Duration, Instant},
+};
+use tracing::error;
+
+use crate::{
+    axum::state::AppState,
+    prisma::{conversation, message},
+};
+use ::clippy::{
+    budget::PassageReport,
+    chat::{ChatMessage, Role},
+    citations::Citation,
+    stream::PartialResult,
+};
+
+/// What the widget answered to a query, collected while the answer is streamed.
+#[derive(Debug, Default)]
+struct Answer {
+    /// Every passage retrieved for the query, including the ones that didn't fit in the prompt.
+    references: Vec<PassageReport>,
+    citations: Vec<Citation>,
+    text: String,
+}
+
+impl Answer {
+    fn record(&mut self, event: &Result<PartialResult>) {
+        match event {
+            Ok(PartialResult::Context(context)) => self.references = context.passages.clone(),
+            Ok(PartialResult::Citations(citations)) => self.citations = citations.clone(),
+            Ok(PartialResult::PartialAnswer(delta)) => self.text.push_str(delta),
+            Ok(PartialResult::Error(_)) | Err(_) => {}
+        }
+    }
+}
+
+/// Rebuilds the chat history of a conversation from its stored messages, oldest first.
+///
+/// Messages without an answer, because it is still being streamed or the client disconnected before any of it, are left out.
+#[must_use]
+pub fn history(messages: &[message::Data]) -> Vec<ChatMessage> {
+    messages
+        .iter()
+        .filter(|message| !message.answer.is_empty())
+        .flat_map(|message| {
+            [
+                ChatMessage::new(Role::User, message.query.clone()),
+                ChatMessage::new(Role::Assistant, message.answer.clone()),
+            ]
+        })
+        .collect()
+}
+
+/// An answer being streamed to the widget, and the question it answers.
+#[derive(Debug)]
+pub struct PendingAnswer {
+    /// The id the answer is stored under, sent to the widget so users can rate it.
+    pub id: String,
+    pub conversation_id: String,
+    pub query: String,
+    pub model: String,
+}
+
+/// Records an answer as it is streamed, and stores it once the stream completes, or is dropped because the client disconnected.
+struct Recorder {
+    state: AppState,
+    id: String,
+    started_at: Instant,
+    /// How long the answer took to complete, if it did.
+    latency: Option<Duration>,
+    answer: Answer,
+}
+
+impl Recorder {
+    fn record(&mut self, event: &Result<PartialResult>) {
+        self.answer.record(event);
+
+        if matches!(event, Ok(PartialResult::Citations(_))) {
+            self.latency = Some(self.started_at.elapsed());
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let state = self.state.clone();
+        let id = mem::take(&mut self.id);
+        let answer = mem::take(&mut self.answer);
+        let latency = self.latency.unwrap_or_else(|| self.started_at.elapsed());
+
+        tokio::spawn(async move {
+            if let Err(e) = save(&state, id, answer, latency).await {
+                error!("Failed to log answer: {e:?}");
+            }
+        });
+    }
+}
+
+/// Stores the query in the conversation, then passes the answer stream through, updating the message with the passages retrieved for it, the final answer and the sources it cites once it ends.
+///
+/// The message is stored before anything is streamed, so the answer can be rated even if the client disconnects midway.
+///
+/// # Errors
+///
+/// This function will return an error if the message could not be stored.
+pub async fn log_answer(
+    state: AppState,
+    pending: PendingAnswer,
+    answer_stream: impl Stream<Item = Result<PartialResult>> + Send + 'static,
+) -> Result<impl Stream<Item = Result<PartialResult>> + Send + 'static> {
+    state
+        .prisma
+        .message()
+        .create(
+            pending.id.clone(),
+            pending.query,
+            String::new(),
+            pending.model,
+            0,
+            conversation::id::equals(pending.conversation_id),
+            vec![],
+        )
+        .exec()
+        .await?;
+
+    let mut recorder = Recorder {
+        state,
+        id: pending.id,
+        started_at: Instant::now(),
+        latency: None,
+        answer: Answer::default(),
+    };
+
+    Ok(answer_stream.inspect(move |event| recorder.record(event)))
+}
+
+async fn save(state: &AppState, id: String, answer: Answer, latency: Duration) -> Result<()> {
+    state
+        .prisma
+        .message()
+        .update(
+            message::id::equals(id),
+            vec![
+                message::answer::set(answer.text),
+                message::latency_ms::set(i32::try_from(latency.as_millis()).unwrap_or(i32::MAX)),
+                message::references::set(serde_json::to_value(answer.references)?),
+                message::citations::set(serde_json::to_value(answer.citations)?),
+            ],
+        )
+        .exec()
+        .await?;
+
+    Ok(())
+}
@@ -8,14 +8,33 @@ type HmacSha256 = Hmac<Sha256>;
 pub fn hmac_sha256<T: AsRef<[u8]>>(data: &T) -> Result<String> {
     let app_key = env::var("APP_KEY").context("APP_KEY not found")?;
 
+    hmac_sha256_with_key(&app_key, data)
+}
+
+pub fn hmac_sha256_with_key<K: AsRef<[u8]>, T: AsRef<[u8]>>(key: &K, data: &T) -> Result<String> {
     Ok(hex::encode(
-        HmacSha256::new_from_slice(app_key.as_bytes())?
+        HmacSha256::new_from_slice(key.as_ref())?
             .chain_update(data)
             .finalize()
             .into_bytes(),
     ))
 }
 
+/// Checks a hex-encoded signature against the data in constant time.
+pub fn verify_hmac_sha256<K: AsRef<[u8]>, T: AsRef<[u8]>>(
+    key: &K,
+    data: &T,
+    signature: &str,
+) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+
+    HmacSha256::new_from_slice(key.as_ref())
+        .map(|mac| mac.chain_update(data).verify_slice(&signature).is_ok())
+        .unwrap_or(false)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -37,4 +56,14 @@ mod tests {
 
         assert_eq!(err.to_string(), "APP_KEY not found");
     }
+
+    #[test]
+    fn verifies_signatures() {
+        let signature = hmac_sha256_with_key(&"hunter2", &"test").unwrap();
+
+        assert!(verify_hmac_sha256(&"hunter2", &"test", &signature));
+        assert!(!verify_hmac_sha256(&"hunter2", &"tampered", &signature));
+        assert!(!verify_hmac_sha256(&"hunter3", &"test", &signature));
+        assert!(!verify_hmac_sha256(&"hunter2", &"test", "not hex"));
+    }
 }
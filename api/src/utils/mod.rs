@@ -1,5 +1,8 @@
+pub mod conversation;
 pub mod crypto;
 pub mod db;
 pub mod email;
 pub mod influx;
 pub mod logger;
+pub mod scheduler;
+pub mod training;
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use std::time::Duration as StdDuration;
+use tracing::{debug, error, info};
+
+use crate::{
+    axum::state::AppState,
+    prisma::{project, TrainingTrigger},
+    utils::training::{self, Queued},
+};
+
+/// How often the scheduler looks for projects that are due for a refresh.
+const TICK: StdDuration = StdDuration::from_secs(60);
+
+/// When a project refreshed every `interval` minutes should next be refreshed, starting from now.
+#[must_use]
+pub fn next_refresh(interval: Option<i32>) -> Option<DateTime<FixedOffset>> {
+    interval.map(|minutes| (Utc::now() + Duration::minutes(minutes.into())).into())
+}
+
+/// Periodically queues a training job for the projects whose refresh is due, so their index follows their documentation.
+pub fn spawn_scheduler(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TICK);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = refresh_due_projects(&state).await {
+                error!("Failed to refresh projects: {e:#}");
+            }
+        }
+    });
+}
+
+async fn refresh_due_projects(state: &AppState) -> Result<()> {
+    let now: DateTime<FixedOffset> = Utc::now().into();
+
+    let projects = state
+        .prisma
+        .project()
+        .find_many(vec![
+            project::refresh_interval::not(None),
+            project::next_refresh_at::lte(now),
+        ])
+        .exec()
+        .await
+        .context("Failed to get projects due for a refresh.")?;
+
+    for project in projects {
+        // Claim the refresh by moving it to the next interval, so other API processes skip it, and a failing refresh isn't retried every tick.
+        let claimed = state
+            .prisma
+            .project()
+            .update_many(
+                vec![
+                    project::id::equals(project.id.clone()),
+                    project::next_refresh_at::equals(project.next_refresh_at),
+                ],
+                vec![project::next_refresh_at::set(next_refresh(
+                    project.refresh_interval,
+                ))],
+            )
+            .exec()
+            .await
+            .context("Failed to schedule the next refresh.")?;
+
+        if claimed == 0 {
+            continue;
+        }
+
+        match training::queue(state, &project.id, TrainingTrigger::Scheduled).await? {
+            Queued::Job(job) => info!("Queued training job {} to refresh {}.", job.id, project.id),
+            Queued::NoSources => {
+                debug!("Skipping the refresh of {}, it has no sources.", project.id)
+            }
+            Queued::InProgress => debug!(
+                "Skipping the refresh of {}, it's already being trained.",
+                project.id
+            ),
+        }
+    }
+
+    Ok(())
+}
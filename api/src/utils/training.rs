@@ -0,0 +1,734 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use prisma_client_rust::{prisma_errors::query_engine::UniqueKeyViolation, Direction};
+use std::{env, fs, path::Path, time::Duration};
+use tokio::{
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    axum::state::AppState,
+    prisma::{
+        project, source, training_job, ProjectStatus, TrainingStage, TrainingStatus,
+        TrainingTrigger,
+    },
+};
+use ::clippy::{
+    index_documents, reindex,
+    train::{parse_documents, Source},
+    update_documents, ChunkConfig, Document, IndexReport, Manifest,
+};
+
+/// How a job updates the project's index.
+enum Indexing {
+    /// Builds a fresh index from every document, and swaps it in once it's ready.
+    Rebuild,
+    /// Embeds what changed across every document in the live index.
+    Incremental,
+    /// Only re-indexes the documents of the files changed by a push, given relative to the repository.
+    Changes(Vec<String>),
+}
+
+/// Sends the ids of queued training jobs to the worker.
+pub type TrainingQueue = UnboundedSender<String>;
+
+/// How often running jobs record that they are still alive.
+const HEARTBEAT: Duration = Duration::from_secs(30);
+/// How long a running job can go without a heartbeat before it's considered interrupted.
+const STALE_AFTER: Duration = Duration::from_secs(2 * 60);
+
+/// What came of queueing a training job.
+#[derive(Debug)]
+pub enum Queued {
+    Job(training_job::Data),
+    /// The project has no sources to train from.
+    NoSources,
+    /// The project already has a job queued or running.
+    InProgress,
+}
+
+/// Changes pushed while a job was running, which it may have fetched the sources too early for.
+///
+/// They're queued as a new job once the running one finishes.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct FollowUp {
+    sources: Vec<serde_json::Value>,
+    /// `None` when a push didn't list every file it changed, so every source has to be indexed again.
+    changes: Option<Vec<String>>,
+}
+
+impl FollowUp {
+    /// Adds the changes pushed to the given sources, without repeating the sources or files it already has.
+    fn merge(mut self, sources: &[serde_json::Value], changes: Option<&[String]>) -> Self {
+        for source in sources {
+            if !self.sources.contains(source) {
+                self.sources.push(source.clone());
+            }
+        }
+
+        self.changes = match (self.changes, changes) {
+            (Some(mut merged), Some(changes)) => {
+                merged.extend_from_slice(changes);
+                merged.sort();
+                merged.dedup();
+                Some(merged)
+            }
+            _ => None,
+        };
+
+        self
+    }
+}
+
+/// Creates a training job from a copy of the project's current sources and sends it to the worker.
+///
+/// Jobs keep a copy of the sources, so they can be audited after the sources change.
+///
+/// # Errors
+///
+/// This function will return an error if the job could not be created or queued.
+pub async fn queue(state: &AppState, project_id: &str, trigger: TrainingTrigger) -> Result<Queued> {
+    let sources = project_sources(state, project_id).await?;
+    if sources.is_empty() {
+        return Ok(Queued::NoSources);
+    }
+
+    Ok(
+        match create(state, project_id, sources, trigger, vec![]).await? {
+            Some(job) => Queued::Job(job),
+            None => Queued::InProgress,
+        },
+    )
+}
+
+/// The configs of the project's sources, oldest first.
+async fn project_sources(state: &AppState, project_id: &str) -> Result<Vec<serde_json::Value>> {
+    let sources = state
+        .prisma
+        .source()
+        .find_many(vec![source::project_id::equals(project_id.to_string())])
+        .order_by(source::created_at::order(Direction::Asc))
+        .exec()
+        .await
+        .context("Failed to get sources.")?;
+
+    Ok(sources.into_iter().map(|source| source.config).collect())
+}
+
+/// Creates a training job that only re-indexes the files a push changed in the given sources, or falls back to training the project from all of its sources if it doesn't have an index to update yet, or if the changed files aren't all known.
+///
+/// If the project already has a job queued or running, the changes are handed to it instead, and that job is returned.
+///
+/// # Errors
+///
+/// This function will return an error if the job could not be created or queued.
+pub async fn queue_changes(
+    state: &AppState,
+    project: &project::Data,
+    sources: Vec<serde_json::Value>,
+    changes: Option<Vec<String>>,
+) -> Result<Option<training_job::Data>> {
+    let indexed = project.index_name.is_some() && project.manifest.is_some();
+    loop {
+        match &changes {
+            Some(changes) if indexed => {
+                if let Some(job) = create(
+                    state,
+                    &project.id,
+                    sources.clone(),
+                    TrainingTrigger::Webhook,
+                    vec![training_job::changes::set(Some(changes.clone().into()))],
+                )
+                .await?
+                {
+                    return Ok(Some(job));
+                }
+            }
+            _ => match queue(state, &project.id, TrainingTrigger::Webhook).await? {
+                Queued::Job(job) => return Ok(Some(job)),
+                Queued::NoSources => return Ok(None),
+                Queued::InProgress => {}
+            },
+        }
+
+        if let Some(job) = hand_over(state, &project.id, &sources, changes.as_deref()).await? {
+            return Ok(Some(job));
+        }
+
+        // The active job finished in the meantime, so a new one can be created.
+    }
+}
+
+/// Hands pushed changes to the project's active job, returning it, or `None` if it finished before it could take them.
+///
+/// Queued jobs fetch the sources once they start, so they only need to know which files to re-index. Running jobs may have fetched them before the push, so they queue a follow-up job once they finish.
+async fn hand_over(
+    state: &AppState,
+    project_id: &str,
+    sources: &[serde_json::Value],
+    changes: Option<&[String]>,
+) -> Result<Option<training_job::Data>> {
+    let Some(job) = state
+        .prisma
+        .training_job()
+        .find_first(vec![training_job::active_for::equals(Some(
+            project_id.to_string(),
+        ))])
+        .exec()
+        .await
+        .context("Failed to get the active training job.")?
+    else {
+        return Ok(None);
+    };
+
+    let params = match (&job.status, &job.changes) {
+        // Jobs training from every source will index the changes anyway.
+        (TrainingStatus::Queued, None) => return Ok(Some(job)),
+        (TrainingStatus::Queued, Some(queued)) => {
+            let merged = FollowUp {
+                sources: serde_json::from_value(job.sources.clone()).context("Invalid sources")?,
+                changes: serde_json::from_value(queued.clone()).context("Invalid changes")?,
+            }
+            .merge(sources, changes);
+            // Indexing every source needs all of them, not just the ones that were pushed to.
+            let sources = match merged.changes {
+                Some(_) => merged.sources,
+                None => project_sources(state, project_id).await?,
+            };
+
+            vec![
+                training_job::sources::set(sources.into()),
+                training_job::changes::set(merged.changes.map(Into::into)),
+            ]
+        }
+        (TrainingStatus::Running, _) => {
+            let follow_up = job
+                .follow_up
+                .clone()
+                .map(serde_json::from_value::<FollowUp>)
+                .transpose()
+                .context("Invalid follow-up")?
+                .unwrap_or(FollowUp {
+                    sources: vec![],
+                    changes: Some(vec![]),
+                })
+                .merge(sources, changes);
+
+            vec![training_job::follow_up::set(Some(
+                serde_json::to_value(follow_up).context("Failed to serialize follow-up")?,
+            ))]
+        }
+        _ => return Ok(None),
+    };
+
+    // The job may have started or finished since it was read, in which case the changes are handed over again.
+    let updated = state
+        .prisma
+        .training_job()
+        .update_many(
+            vec![
+                training_job::id::equals(job.id.clone()),
+                training_job::status::equals(job.status),
+            ],
+            params,
+        )
+        .exec()
+        .await
+        .context("Failed to update training job.")?;
+
+    Ok((updated > 0).then_some(job))
+}
+
+/// Creates a job and sends it to the worker, or returns `None` if the project already has a job queued or running.
+async fn create(
+    state: &AppState,
+    project_id: &str,
+    sources: Vec<serde_json::Value>,
+    trigger: TrainingTrigger,
+    mut params: Vec<training_job::SetParam>,
+) -> Result<Option<training_job::Data>> {
+    let id = state
+        .pika
+        .clone()
+        .gen("train")
+        .context("Failed to generate training job id.")?;
+
+    params.extend([
+        training_job::trigger::set(trigger),
+        training_job::active_for::set(Some(project_id.to_string())),
+    ]);
+    let job = match state
+        .prisma
+        .training_job()
+        .create(
+            id,
+            serde_json::Value::Array(sources),
+            project::id::equals(project_id.to_string()),
+            params,
+        )
+        .exec()
+        .await
+    {
+        Ok(job) => job,
+        // Only one job per project can be active, and another one got there first.
+        Err(e) if e.is_prisma_error::<UniqueKeyViolation>() => return Ok(None),
+        Err(e) => return Err(e).context("Failed to create training job."),
+    };
+
+    state
+        .training
+        .send(job.id.clone())
+        .context("Failed to queue training job.")?;
+
+    Ok(Some(job))
+}
+
+/// Queues the changes pushed while a job was running, once it's done and the project can have a new job.
+async fn follow_up(state: &AppState, job: training_job::Data) -> Result<()> {
+    let Some(follow_up) = job.follow_up else {
+        return Ok(());
+    };
+    let follow_up: FollowUp = serde_json::from_value(follow_up).context("Invalid follow-up")?;
+
+    let project = state
+        .prisma
+        .project()
+        .find_unique(project::id::equals(job.project_id))
+        .exec()
+        .await?
+        .ok_or_else(|| anyhow!("Project not found"))?;
+
+    if let Some(queued) =
+        queue_changes(state, &project, follow_up.sources, follow_up.changes).await?
+    {
+        info!(
+            "Queued training job {} to follow up on {}.",
+            queued.id, job.id
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs queued training jobs one at a time in the background, starting with the ones left queued by a previous run.
+///
+/// Several API processes can run a worker, as jobs are claimed before running, and only the running jobs that stopped sending heartbeats are failed.
+pub fn spawn_worker(state: AppState, mut jobs: UnboundedReceiver<String>) {
+    let sweeper = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEARTBEAT);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = fail_stale(&sweeper).await {
+                error!("Failed to fail interrupted training jobs: {e:#}");
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        if let Err(e) = resume(&state).await {
+            error!("Failed to resume training jobs: {e:#}");
+        }
+
+        while let Some(job_id) = jobs.recv().await {
+            // Jobs run in their own task, so a panic fails the job instead of stopping the worker.
+            let error = match tokio::spawn(run(state.clone(), job_id.clone())).await {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => format!("{e:#}"),
+                Err(e) => e.to_string(),
+            };
+
+            error!("Training job {job_id} failed: {error}");
+            if let Err(e) = fail(&state, &job_id, error).await {
+                error!("Failed to record the failure of training job {job_id}: {e:#}");
+            }
+        }
+    });
+}
+
+/// Queues the jobs nobody got to yet. Jobs other workers are about to run are skipped once they claimed them.
+async fn resume(state: &AppState) -> Result<()> {
+    let queued = state
+        .prisma
+        .training_job()
+        .find_many(vec![training_job::status::equals(TrainingStatus::Queued)])
+        .order_by(training_job::created_at::order(Direction::Asc))
+        .exec()
+        .await?;
+
+    for job in queued {
+        state.training.send(job.id)?;
+    }
+
+    Ok(())
+}
+
+/// Fails the running jobs that stopped sending heartbeats, as the process running them crashed or was restarted.
+async fn fail_stale(state: &AppState) -> Result<()> {
+    let cutoff: DateTime<FixedOffset> =
+        (Utc::now() - chrono::Duration::from_std(STALE_AFTER)?).into();
+
+    let stale = state
+        .prisma
+        .training_job()
+        .find_many(vec![
+            training_job::status::equals(TrainingStatus::Running),
+            training_job::heartbeat_at::lt(cutoff),
+        ])
+        .exec()
+        .await?;
+
+    for job in stale {
+        // The job may have sent a heartbeat or been failed by another process since it was listed.
+        let failed = state
+            .prisma
+            .training_job()
+            .update_many(
+                vec![
+                    training_job::id::equals(job.id.clone()),
+                    training_job::status::equals(TrainingStatus::Running),
+                    training_job::heartbeat_at::equals(job.heartbeat_at),
+                ],
+                vec![
+                    training_job::status::set(TrainingStatus::Failed),
+                    training_job::error::set(Some("Interrupted by a restart.".to_string())),
+                    training_job::finished_at::set(Some(Utc::now().into())),
+                    training_job::active_for::set(None),
+                ],
+            )
+            .exec()
+            .await?;
+
+        if failed == 0 {
+            continue;
+        }
+
+        warn!(
+            "Training job {} stopped responding, marking it as failed.",
+            job.id
+        );
+
+        // Changes may have been handed to the job since it was listed.
+        let job = state
+            .prisma
+            .training_job()
+            .find_unique(training_job::id::equals(job.id))
+            .exec()
+            .await?
+            .ok_or_else(|| anyhow!("Training job not found"))?;
+        fail_project(state, job).await?;
+    }
+
+    Ok(())
+}
+
+/// Records a heartbeat for a running job until dropped.
+struct Heartbeat(JoinHandle<()>);
+
+impl Heartbeat {
+    fn start(state: AppState, job_id: String) -> Self {
+        Self(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT);
+
+            loop {
+                interval.tick().await;
+
+                let beat = vec![training_job::heartbeat_at::set(Some(Utc::now().into()))];
+                if let Err(e) = update(&state, &job_id, beat).await {
+                    error!("Failed to record the heartbeat of training job {job_id}: {e:#}");
+                }
+            }
+        }))
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn run(state: AppState, job_id: String) -> Result<()> {
+    // Claim the job first, as other workers may have been sent it too.
+    let now: DateTime<FixedOffset> = Utc::now().into();
+    let claimed = state
+        .prisma
+        .training_job()
+        .update_many(
+            vec![
+                training_job::id::equals(job_id.clone()),
+                training_job::status::equals(TrainingStatus::Queued),
+            ],
+            vec![
+                training_job::status::set(TrainingStatus::Running),
+                training_job::started_at::set(Some(now)),
+                training_job::heartbeat_at::set(Some(now)),
+            ],
+        )
+        .exec()
+        .await?;
+
+    if claimed == 0 {
+        debug!("Skipping training job {job_id}, it was already claimed.");
+        return Ok(());
+    }
+    let _heartbeat = Heartbeat::start(state.clone(), job_id.clone());
+
+    let job = state
+        .prisma
+        .training_job()
+        .find_unique(training_job::id::equals(job_id.clone()))
+        .exec()
+        .await?
+        .ok_or_else(|| anyhow!("Training job {job_id} not found"))?;
+
+    let project = state
+        .prisma
+        .project()
+        .find_unique(project::id::equals(job.project_id.clone()))
+        .exec()
+        .await?
+        .ok_or_else(|| anyhow!("Project {} not found", job.project_id))?;
+
+    let sources: Vec<Source> = serde_json::from_value(job.sources).context("Invalid sources")?;
+    let changes: Option<Vec<String>> = job
+        .changes
+        .map(serde_json::from_value)
+        .transpose()
+        .context("Invalid changes")?;
+    // Scheduled refreshes and pushes only embed what changed in the live index, while manual trainings and indexes without a manifest are rebuilt from scratch.
+    let indexed = project.index_name.is_some() && project.manifest.is_some();
+    let indexing = match changes {
+        // Jobs with changes only have the sources that were pushed to, so they can't rebuild the index.
+        Some(_) if !indexed => bail!("The project has no index to update"),
+        Some(changes) => Indexing::Changes(changes),
+        None if indexed && job.trigger != TrainingTrigger::Manual => Indexing::Incremental,
+        None => Indexing::Rebuild,
+    };
+    let mut manifest: Manifest = project
+        .manifest
+        .map(serde_json::from_value)
+        .transpose()
+        .context("Invalid manifest")?
+        .unwrap_or_default();
+    // Projects keep their index name across trainings, as the alias is swapped to the new collection once it's ready.
+    let alias = project
+        .index_name
+        .unwrap_or_else(|| format!("docs_{}", project.id));
+
+    state
+        .prisma
+        .project()
+        .update(
+            project::id::equals(project.id.clone()),
+            vec![project::status::set(ProjectStatus::Training)],
+        )
+        .exec()
+        .await?;
+
+    let dir = env::temp_dir().join("clippy").join(&job_id);
+    let result = train(
+        &state,
+        &job_id,
+        &sources,
+        &dir,
+        &alias,
+        &mut manifest,
+        indexing,
+    )
+    .await;
+    fs::remove_dir_all(&dir).ok();
+
+    // Incremental indexing updates the manifest as it goes, so it's saved even if indexing failed midway.
+    let sections = manifest.sections();
+    let manifest = serde_json::to_value(&manifest).context("Failed to serialize manifest")?;
+    state
+        .prisma
+        .project()
+        .update(
+            project::id::equals(project.id.clone()),
+            vec![project::manifest::set(Some(manifest))],
+        )
+        .exec()
+        .await?;
+    result?;
+
+    let job = state
+        .prisma
+        .training_job()
+        .update(
+            training_job::id::equals(job_id.clone()),
+            vec![
+                training_job::status::set(TrainingStatus::Succeeded),
+                training_job::sections::set(Some(count(sections))),
+                training_job::finished_at::set(Some(Utc::now().into())),
+                training_job::active_for::set(None),
+            ],
+        )
+        .exec()
+        .await?;
+    state
+        .prisma
+        .project()
+        .update(
+            project::id::equals(project.id),
+            vec![
+                project::status::set(ProjectStatus::Trained),
+                project::index_name::set(Some(alias.clone())),
+                project::trained_at::set(Some(Utc::now().into())),
+            ],
+        )
+        .exec()
+        .await?;
+
+    state.answers.invalidate(&alias);
+    state.retriever.keywords().invalidate(&alias);
+
+    info!("Training job {job_id} trained {alias}.");
+
+    // The job succeeded, so failing to queue its follow-up shouldn't fail it.
+    if let Err(e) = follow_up(&state, job).await {
+        error!("Failed to follow up on training job {job_id}: {e:#}");
+    }
+
+    Ok(())
+}
+
+/// Downloads, parses and embeds the documentation of every source, recording each stage as it starts.
+async fn train(
+    state: &AppState,
+    job_id: &str,
+    sources: &[Source],
+    dir: &Path,
+    alias: &str,
+    manifest: &mut Manifest,
+    indexing: Indexing,
+) -> Result<IndexReport> {
+    set_stage(state, job_id, TrainingStage::Fetching, vec![]).await?;
+    let mut roots = Vec::with_capacity(sources.len());
+    let mut removed = Vec::new();
+    for (i, source) in sources.iter().enumerate() {
+        let root = source.download(&dir.join(i.to_string()), true).await?;
+        // Documents are prefixed with their source, so sources with the same files don't overwrite each other's sections.
+        let slug = source.slug();
+
+        if let Indexing::Changes(changes) = &indexing {
+            removed.extend(
+                source
+                    .retain_changed(&root, changes)?
+                    .into_iter()
+                    .map(|path| format!("/{slug}{path}")),
+            );
+        }
+        roots.push((slug, root));
+    }
+
+    set_stage(state, job_id, TrainingStage::Parsing, vec![]).await?;
+    let documents = tokio::task::spawn_blocking(move || {
+        roots
+            .iter()
+            .try_fold(Vec::new(), |mut documents, (slug, root)| {
+                documents.extend(
+                    parse_documents(root, &ChunkConfig::default())?
+                        .into_iter()
+                        .map(|document| Document {
+                            path: format!("/{slug}{}", document.path),
+                            ..document
+                        }),
+                );
+                anyhow::Ok(documents)
+            })
+    })
+    .await??;
+
+    set_stage(
+        state,
+        job_id,
+        TrainingStage::Embedding,
+        vec![training_job::documents::set(Some(count(documents.len())))],
+    )
+    .await?;
+
+    let embedder = state.retriever.embedder.as_ref();
+    let store = state.retriever.store.as_ref();
+    match indexing {
+        Indexing::Rebuild => reindex(embedder, store, alias, manifest, &documents).await,
+        Indexing::Incremental => {
+            index_documents(embedder, store, alias, manifest, &documents).await
+        }
+        Indexing::Changes(_) => {
+            update_documents(embedder, store, alias, manifest, &documents, &removed).await
+        }
+    }
+}
+
+async fn set_stage(
+    state: &AppState,
+    job_id: &str,
+    stage: TrainingStage,
+    mut params: Vec<training_job::SetParam>,
+) -> Result<()> {
+    params.push(training_job::stage::set(Some(stage)));
+
+    update(state, job_id, params).await
+}
+
+async fn update(state: &AppState, job_id: &str, params: Vec<training_job::SetParam>) -> Result<()> {
+    state
+        .prisma
+        .training_job()
+        .update(training_job::id::equals(job_id.to_string()), params)
+        .exec()
+        .await
+        .context("Failed to update training job.")?;
+
+    Ok(())
+}
+
+/// Marks a job and its project as failed. Projects that were trained before keep their index, so they're still answered from it.
+async fn fail(state: &AppState, job_id: &str, error: String) -> Result<()> {
+    let job = state
+        .prisma
+        .training_job()
+        .update(
+            training_job::id::equals(job_id.to_string()),
+            vec![
+                training_job::status::set(TrainingStatus::Failed),
+                training_job::error::set(Some(error)),
+                training_job::finished_at::set(Some(Utc::now().into())),
+                training_job::active_for::set(None),
+            ],
+        )
+        .exec()
+        .await?;
+
+    fail_project(state, job).await
+}
+
+/// Marks the project of a failed job as failed, and queues the changes pushed while the job ran.
+async fn fail_project(state: &AppState, job: training_job::Data) -> Result<()> {
+    state
+        .prisma
+        .project()
+        .update(
+            project::id::equals(job.project_id.clone()),
+            vec![project::status::set(ProjectStatus::Failed)],
+        )
+        .exec()
+        .await?;
+
+    let job_id = job.id.clone();
+    if let Err(e) = follow_up(state, job).await {
+        error!("Failed to follow up on training job {job_id}: {e:#}");
+    }
+
+    Ok(())
+}
+
+fn count(n: usize) -> i32 {
+    i32::try_from(n).unwrap_or(i32::MAX)
+}
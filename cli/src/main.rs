@@ -1,40 +1,149 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use crawler::{Config, Website};
+use clap::{Parser, Subcommand, ValueEnum};
 use dotenvy::dotenv;
-use html2md::parse_html;
-use readability::extractor::extract;
-use reqwest::Client;
 use std::{
-    fs::{self, DirEntry},
-    io::Cursor,
+    fs,
     path::{Path, PathBuf},
     process,
+    sync::Arc,
 };
-use tracing::debug;
 use tracing_subscriber::{
     prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt, EnvFilter,
 };
 
-use ::clippy::{build_messages, into_document, search_project, Document, OpenAI, Qdrant};
+use ::clippy::{
+    build_messages, citations, index_documents, into_document, reindex,
+    store::Filter,
+    train::{read_dir_recursive, Source, SourceKind},
+    CachedEmbedder, ChatModel, ChatSettings, ChunkConfig, Document, Embedder, HashEmbedder,
+    LexicalReranker, LlmReranker, LocalStore, Manifest, OpenAI, PromptTemplate, Qdrant, Reranker,
+    Retriever, SearchOptions, VectorStore,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// The model used to embed documents and queries.
+    #[arg(long, value_enum, global = true, default_value_t = EmbedderKind::OpenAI)]
+    embedder: EmbedderKind,
+
+    /// Where embedded documents are stored.
+    #[arg(long, value_enum, global = true, default_value_t = StoreKind::Qdrant)]
+    store: StoreKind,
+
+    /// The directory used to persist the local store.
+    #[arg(long, global = true, default_value = "build/.store")]
+    store_path: PathBuf,
+
+    /// The directory used to cache embeddings, so identical text is only embedded once.
+    #[arg(long, global = true, default_value = "build/.embeddings")]
+    embedding_cache: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum EmbedderKind {
+    #[value(name = "openai")]
+    OpenAI,
+    Local,
+}
+
+impl EmbedderKind {
+    fn build(self) -> Box<dyn Embedder> {
+        match self {
+            Self::OpenAI => Box::new(OpenAI::new()),
+            Self::Local => Box::new(HashEmbedder::default()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum StoreKind {
+    Qdrant,
+    Local,
+}
+
+impl StoreKind {
+    fn build(self, path: &Path) -> Result<Box<dyn VectorStore>> {
+        Ok(match self {
+            Self::Qdrant => Box::new(Qdrant::from_env()?),
+            Self::Local => Box::new(LocalStore::open(path)?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum RerankerKind {
+    None,
+    Lexical,
+    Llm,
+}
+
+impl RerankerKind {
+    fn build(self) -> Option<Arc<dyn Reranker>> {
+        match self {
+            Self::None => None,
+            Self::Lexical => Some(Arc::new(LexicalReranker::default())),
+            Self::Llm => Some(Arc::new(LlmReranker::new(Arc::new(OpenAI::new())))),
+        }
+    }
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
-    Embed { slug: String },
-    Process { slug: String },
-    Ask { slug: String, query: String },
-    Fetch { slug: String, repo: String },
-    Query { slug: String, query: String },
-    Crawl { slug: String, base_url: String },
+    Embed {
+        slug: String,
+        /// Embed every section into a new index and swap it in once it's ready, instead of only embedding the sections that changed.
+        #[arg(long)]
+        rebuild: bool,
+    },
+    Process {
+        slug: String,
+        /// The number of tokens each chunk should aim for.
+        #[arg(long, default_value_t = ChunkConfig::default().target_tokens)]
+        chunk_tokens: usize,
+        /// The number of tokens shared between neighbouring chunks.
+        #[arg(long, default_value_t = ChunkConfig::default().overlap)]
+        chunk_overlap: usize,
+        /// The number of tokens no chunk will exceed.
+        #[arg(long, default_value_t = ChunkConfig::default().max_tokens)]
+        max_chunk_tokens: usize,
+    },
+    Ask {
+        slug: String,
+        query: String,
+    },
+    Fetch {
+        slug: String,
+        repo: String,
+        /// The branch to fetch the documentation from.
+        #[arg(long, default_value = "main")]
+        branch: String,
+    },
+    Query {
+        slug: String,
+        query: String,
+        /// How much keyword matches weigh against vector similarity, from 0 to 1.
+        #[arg(long, default_value_t = SearchOptions::default().keyword_weight)]
+        keyword_weight: f32,
+        /// How the retrieved candidates are reranked before keeping the best ones.
+        #[arg(long, value_enum, default_value_t = RerankerKind::None)]
+        reranker: RerankerKind,
+        /// Only search pages under this path, like `/api/`.
+        #[arg(long)]
+        path_prefix: Option<String>,
+    },
+    Crawl {
+        slug: String,
+        base_url: String,
+        /// The number of pages to crawl at most.
+        #[arg(long)]
+        max_pages: Option<usize>,
+    },
 }
 
 #[allow(clippy::too_many_lines)]
@@ -49,106 +158,71 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    let embedder = Arc::new(
+        CachedEmbedder::persisted(cli.embedder.build().into(), &cli.embedding_cache)
+            .expect("Failed to load embedding cache"),
+    );
+    let store = cli
+        .store
+        .build(&cli.store_path)
+        .expect("Failed to initialize vector store");
+
     match cli.command {
-        Commands::Fetch { slug, repo } => {
+        Commands::Fetch { slug, repo, branch } => {
             if fs::metadata(format!("build/{slug}")).is_ok() {
                 eprintln!("Error: Directory already exists");
                 process::exit(1);
             }
 
-            fs::create_dir_all(format!("build/{slug}")).expect("Failed to create directory");
-
-            let client = Client::new();
-            let repo_url = format!("https://github.com/{repo}/archive/refs/heads/main.zip");
-
-            let archive = client
-                .get(&repo_url)
-                .send()
-                .await
-                .expect("Failed to fetch repository")
-                .error_for_status()
-                .unwrap()
-                .bytes()
-                .await
-                .unwrap();
-
-            zip_extract::extract(
-                Cursor::new(archive),
-                &PathBuf::from(format!("build/{slug}")),
-                true,
-            )
-            .expect("Failed to extract zip file");
-
-            preprocess_archive(format!("build/{slug}")).unwrap();
-
-            let qdrant = Qdrant::new();
-            qdrant
-                .create_collection(&format!("docs_{slug}"))
-                .await
-                .unwrap();
+            Source::from(SourceKind::GitHub {
+                repo,
+                branch,
+                path: None,
+            })
+            .download(Path::new(&format!("build/{slug}")), false)
+            .await
+            .expect("Failed to fetch repository");
         }
-        Commands::Crawl { slug, base_url } => {
+        Commands::Crawl {
+            slug,
+            base_url,
+            max_pages,
+        } => {
             if fs::metadata(format!("build/{slug}")).is_ok() {
                 eprintln!("Error: Directory already exists");
                 process::exit(1);
             }
 
-            fs::create_dir_all(format!("build/{slug}")).expect("Failed to create directory");
-
-            let mut website =
-                Website::new(&base_url, Config::default()).expect("Failed to create website");
-
-            let build_path = format!("build/{slug}");
-            website
-                .crawl(move |url, html| {
-                    let build_path = build_path.clone();
-
-                    async move {
-                        let path = url.path();
-                        let doc = extract(&mut html.as_bytes(), &url).unwrap();
-                        let mut markdown = parse_html(&doc.content);
-
-                        if !doc.title.is_empty() {
-                            markdown = format!("---\ntitle: \"{}\"\n---\n{}", doc.title, markdown);
-                        }
-
-                        if markdown.is_empty() {
-                            debug!("Skipping empty page: {url}");
-                            return;
-                        }
-
-                        let file_path = PathBuf::from(format!(
-                            "{}{path}{}.md",
-                            &build_path,
-                            if path.ends_with('/') { "index" } else { "" }
-                        ));
-
-                        if let Some(parent) = file_path.parent() {
-                            fs::create_dir_all(parent).unwrap();
-                        }
-                        fs::write(file_path, markdown).unwrap();
-                    }
-                })
-                .await
-                .expect("Failed to crawl website");
-
-            preprocess_archive(format!("build/{slug}")).unwrap();
-
-            let qdrant = Qdrant::new();
-            qdrant
-                .create_collection(&format!("docs_{slug}"))
-                .await
-                .unwrap();
+            Source::from(SourceKind::Website {
+                url: base_url,
+                max_pages,
+            })
+            .download(Path::new(&format!("build/{slug}")), false)
+            .await
+            .expect("Failed to crawl website");
         }
-        Commands::Process { slug } => {
+        Commands::Process {
+            slug,
+            chunk_tokens,
+            chunk_overlap,
+            max_chunk_tokens,
+        } => {
             if fs::metadata(format!("build/{slug}")).is_err() {
                 eprintln!("Error: Project does not exist");
                 process::exit(1);
             }
 
+            let config = match ChunkConfig::new(chunk_tokens, chunk_overlap, max_chunk_tokens) {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("Error: {err}");
+                    process::exit(1);
+                }
+            };
+
             let files = read_dir_recursive(format!("build/{slug}")).unwrap();
             for file in files {
-                let document = into_document(&file, format!("build/{slug}")).unwrap();
+                let document = into_document(&file, format!("build/{slug}"), &config).unwrap();
 
                 if !document.sections.is_empty() {
                     fs::write(
@@ -161,94 +235,143 @@ async fn main() {
                 fs::remove_file(file.path()).unwrap();
             }
         }
-        Commands::Embed { slug } => {
+        Commands::Embed { slug, rebuild } => {
             if fs::metadata(format!("build/{slug}")).is_err() {
                 eprintln!("Error: Project does not exist");
                 process::exit(1);
             }
 
-            let client = OpenAI::new();
-            let qdrant = Qdrant::new().collection(&format!("docs_{slug}"));
-            let files = read_dir_recursive(format!("build/{slug}")).unwrap();
+            let documents = read_dir_recursive(format!("build/{slug}"))
+                .unwrap()
+                .into_iter()
+                .map(|file| {
+                    let document = fs::read_to_string(file.path()).unwrap();
+                    serde_json::from_str::<Document>(&document).unwrap()
+                })
+                .collect::<Vec<_>>();
+
+            let manifest_path = format!("build/{slug}.manifest.json");
+            let mut manifest = Manifest::load(&manifest_path).unwrap();
+
+            let collection = format!("docs_{slug}");
+            // There's nothing to update in place until the index has been built once.
+            let exists = store.alias(&collection).await.unwrap().is_some()
+                || store.collection_exists(&collection).await.unwrap();
+
+            let report = if rebuild || !exists {
+                reindex(
+                    embedder.as_ref(),
+                    store.as_ref(),
+                    &collection,
+                    &mut manifest,
+                    &documents,
+                )
+                .await
+            } else {
+                index_documents(
+                    embedder.as_ref(),
+                    store.as_ref(),
+                    &collection,
+                    &mut manifest,
+                    &documents,
+                )
+                .await
+            }
+            .unwrap();
 
-            for file in files {
-                let document = fs::read_to_string(file.path()).unwrap();
-                let document: Document = serde_json::from_str(&document).unwrap();
+            manifest.save(&manifest_path).unwrap();
 
-                let points = client.embed(&document).await.unwrap();
+            println!(
+                "Added {} sections, updated {}, removed {} ({} unchanged).",
+                report.added, report.updated, report.removed, report.unchanged
+            );
 
-                qdrant.upsert(&points).await.unwrap();
-            }
+            let info = store.collection_info(&collection).await.unwrap();
+            println!("{collection} now has {} sections.", info.points);
+            report_embedding_cache(&embedder);
         }
-        Commands::Query { slug, query } => {
+        Commands::Query {
+            slug,
+            query,
+            keyword_weight,
+            reranker,
+            path_prefix,
+        } => {
             if fs::metadata(format!("build/{slug}")).is_err() {
                 eprintln!("Error: Project does not exist");
                 process::exit(1);
             }
 
-            let results = search_project(&format!("docs_{slug}"), &query, 3)
+            let mut retriever = Retriever::new(embedder.clone(), store.into());
+            if let Some(reranker) = reranker.build() {
+                retriever = retriever.with_reranker(reranker);
+            }
+
+            let results = retriever
+                .search_project(
+                    &format!("docs_{slug}"),
+                    &query,
+                    &SearchOptions {
+                        count: 3,
+                        keyword_weight,
+                        filter: Filter {
+                            path_prefix,
+                            ..Filter::default()
+                        },
+                        ..SearchOptions::default()
+                    },
+                )
                 .await
                 .unwrap();
 
             println!("{results:?}");
+            report_embedding_cache(&embedder);
         }
         Commands::Ask { slug, query } => {
             let client = OpenAI::new();
-            let qdrant = Qdrant::new().collection(&format!("docs_{slug}"));
 
-            let query_points = client.raw_embed(&query).await.unwrap();
-            let results = qdrant.query(query_points, 3).await.unwrap();
+            let query_points = embedder.raw_embed(&query).await.unwrap();
+            let results = store
+                .query(&format!("docs_{slug}"), query_points, 3, &Filter::default())
+                .await
+                .unwrap();
             let response = client
-                .chat(build_messages(
-                    &query,
-                    &results.iter().map(Into::into).collect::<Vec<_>>(),
-                ))
+                .chat(
+                    build_messages(
+                        &PromptTemplate::default(),
+                        &slug,
+                        &query,
+                        &results.iter().map(Into::into).collect::<Vec<_>>(),
+                        &[],
+                    ),
+                    &ChatSettings::default(),
+                )
                 .await
                 .unwrap();
 
             println!("{response:?}");
-        }
-    }
-}
 
-fn preprocess_archive<P: AsRef<Path>>(path: P) -> Result<()> {
-    let extensions = vec!["md", "mdx"];
+            let sources = results.into_iter().map(|r| r.payload).collect::<Vec<_>>();
+            for citation in citations::parse(&response, &sources) {
+                println!(
+                    "[{}] {} ({})",
+                    citation.number, citation.source.page_title, citation.source.path
+                );
+            }
 
-    map_dir(path, &|file| {
-        if file
-            .path()
-            .extension()
-            .map_or(true, |ext| !extensions.contains(&ext.to_str().unwrap()))
-        {
-            fs::remove_file(file.path())?;
+            report_embedding_cache(&embedder);
         }
-
-        Ok(())
-    })
-}
-
-fn read_dir_recursive<P: AsRef<Path>>(path: P) -> Result<Vec<DirEntry>> {
-    let files = fs::read_dir(path)?.collect::<Result<Vec<_>, std::io::Error>>()?;
-
-    Ok(files
-        .into_iter()
-        .flat_map(|entry| {
-            if entry.path().is_dir() {
-                read_dir_recursive(entry.path())
-            } else {
-                Ok(vec![entry])
-            }
-        })
-        .flatten()
-        .collect())
+    }
 }
 
-fn map_dir<P: AsRef<Path>>(path: P, cb: &impl Fn(DirEntry) -> Result<()>) -> Result<()> {
-    let files = read_dir_recursive(path)?;
-
-    for file in files {
-        cb(file)?;
-    }
+fn report_embedding_cache(embedder: &CachedEmbedder) {
+    embedder.save().expect("Failed to save embedding cache");
 
-    Ok(())
+    let stats = embedder.stats();
+    println!(
+        "Embedding cache: {} hits, {} misses ({:.0}% hit rate).",
+        stats.hits,
+        stats.misses,
+        stats.hit_rate() * 100.0
+    );
 }
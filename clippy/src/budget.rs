@@ -0,0 +1,205 @@
+use crate::{
+    store::{Payload, PointResult},
+    tokens, Context,
+};
+
+/// Passages that would have fewer tokens of content left after truncation are dropped instead.
+const MIN_PASSAGE_TOKENS: usize = 32;
+
+/// Limits how much of the prompt the retrieved documentation can take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextBudget {
+    /// The number of tokens all passages can take together.
+    pub max_tokens: usize,
+    /// The number of tokens a single passage can take, so one long section can't crowd out the others.
+    pub max_passage_tokens: usize,
+}
+
+impl Default for ContextBudget {
+    fn default() -> Self {
+        Self {
+            max_tokens: 2_000,
+            max_passage_tokens: 600,
+        }
+    }
+}
+
+/// How a retrieved passage made it into the prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Inclusion {
+    Included,
+    Truncated,
+    Dropped,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PassageReport {
+    pub path: String,
+    pub title: String,
+    pub inclusion: Inclusion,
+    /// The number of tokens the passage takes in the prompt.
+    pub tokens: usize,
+}
+
+/// What the documentation part of a prompt was made of.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ContextReport {
+    pub budget: usize,
+    pub used: usize,
+    /// Every retrieved passage, in ranked order.
+    pub passages: Vec<PassageReport>,
+}
+
+/// The passages that fit in the budget, ready to be numbered in the prompt.
+#[derive(Debug, Clone, Default)]
+pub struct AssembledContext {
+    pub contexts: Vec<Context>,
+    /// The payloads of the included passages, in the same order as `contexts`, so citations can be resolved.
+    pub sources: Vec<Payload>,
+    pub report: ContextReport,
+}
+
+/// Counts the tokens a passage takes once numbered in the prompt.
+fn count(number: usize, context: &Context) -> usize {
+    tokens::count(&format!("[{number}] {context}\n"))
+}
+
+/// Truncates a passage's content so it takes at most `max_tokens` in the prompt, or returns `None` if too little of it would be left.
+fn truncate(number: usize, context: &Context, max_tokens: usize) -> Option<(Context, usize)> {
+    let overhead = count(
+        number,
+        &Context {
+            content: String::new(),
+            ..context.clone()
+        },
+    );
+
+    let mut available = max_tokens.checked_sub(overhead)?;
+    loop {
+        if available < MIN_PASSAGE_TOKENS {
+            return None;
+        }
+
+        let truncated = Context {
+            content: tokens::truncate(&context.content, available),
+            ..context.clone()
+        };
+
+        // Tokens can merge differently around the cut, so keep cutting until it really fits.
+        let tokens = count(number, &truncated);
+        if tokens <= max_tokens {
+            return Some((truncated, tokens));
+        }
+
+        available -= tokens - max_tokens;
+    }
+}
+
+/// Fills the budget with the retrieved passages in ranked order, truncating the ones that are too long and dropping the ones that no longer fit.
+#[must_use]
+pub fn assemble(results: Vec<PointResult>, budget: &ContextBudget) -> AssembledContext {
+    let mut assembled = AssembledContext {
+        report: ContextReport {
+            budget: budget.max_tokens,
+            ..ContextReport::default()
+        },
+        ..AssembledContext::default()
+    };
+
+    for result in results {
+        let context = Context::from(&result);
+        let number = assembled.contexts.len() + 1;
+        let allowed = budget
+            .max_passage_tokens
+            .min(budget.max_tokens - assembled.report.used);
+
+        let tokens = count(number, &context);
+        let fitted = if tokens <= allowed {
+            Some((Inclusion::Included, context, tokens))
+        } else {
+            truncate(number, &context, allowed)
+                .map(|(context, tokens)| (Inclusion::Truncated, context, tokens))
+        };
+
+        let (inclusion, tokens) = match fitted {
+            Some((inclusion, context, tokens)) => {
+                assembled.contexts.push(context);
+                assembled.sources.push(result.payload.clone());
+                assembled.report.used += tokens;
+                (inclusion, tokens)
+            }
+            None => (Inclusion::Dropped, 0),
+        };
+
+        assembled.report.passages.push(PassageReport {
+            inclusion,
+            tokens,
+            path: result.payload.path,
+            title: result.payload.title,
+        });
+    }
+
+    assembled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(path: &str, words: usize) -> PointResult {
+        PointResult {
+            id: path.to_string(),
+            score: 1.0,
+            payload: Payload {
+                path: path.to_string(),
+                text: "word ".repeat(words),
+                ..Payload::default()
+            },
+        }
+    }
+
+    #[test]
+    fn fills_budget_in_ranked_order() {
+        // Given
+        let budget = ContextBudget {
+            max_tokens: 220,
+            max_passage_tokens: 120,
+        };
+        let results = vec![
+            result("/short", 50),
+            result("/long", 500),
+            result("/dropped", 100),
+            result("/tiny", 5),
+        ];
+
+        // When
+        let assembled = assemble(results, &budget);
+
+        // Then
+        assert_eq!(
+            assembled
+                .report
+                .passages
+                .iter()
+                .map(|p| (p.path.as_str(), p.inclusion))
+                .collect::<Vec<_>>(),
+            vec![
+                ("/short", Inclusion::Included),
+                ("/long", Inclusion::Truncated),
+                ("/dropped", Inclusion::Dropped),
+                ("/tiny", Inclusion::Included),
+            ]
+        );
+        assert!(assembled.report.used <= budget.max_tokens);
+        assert!(assembled.report.passages[1].tokens <= budget.max_passage_tokens);
+        assert_eq!(
+            assembled
+                .sources
+                .iter()
+                .map(|s| s.path.as_str())
+                .collect::<Vec<_>>(),
+            vec!["/short", "/long", "/tiny"]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use crate::stream::{PartialResult, Question};
+
+/// The cosine similarity above which two questions get the same answer by default.
+pub const DEFAULT_THRESHOLD: f32 = 0.97;
+/// How long answers are cached for by default.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many answers are kept per project. The oldest ones are evicted first.
+const MAX_ENTRIES_PER_PROJECT: usize = 256;
+
+#[derive(Debug)]
+struct Entry {
+    /// The question the answer was given to, without its query, so answers are only replayed with the same settings.
+    question: Question,
+    embedding: Vec<f32>,
+    /// The generation of the index the answer was given from, like when the project was last trained.
+    generation: String,
+    events: Vec<PartialResult>,
+    cached_at: Instant,
+}
+
+/// Remembers the answers to standalone questions, replaying them for new questions whose embedding is close enough.
+#[derive(Debug)]
+pub struct AnswerCache {
+    /// The cosine similarity above which two questions are considered the same.
+    threshold: f32,
+    ttl: Duration,
+    entries: RwLock<HashMap<String, Vec<Entry>>>,
+}
+
+/// Scales a vector to unit length, so cosine similarity becomes a dot product.
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for x in &mut vector {
+            *x /= norm;
+        }
+    }
+
+    vector
+}
+
+/// The question without its query, as cached answers are keyed by the query's embedding instead.
+fn without_query(question: &Question) -> Question {
+    Question {
+        query: String::new(),
+        ..question.clone()
+    }
+}
+
+impl AnswerCache {
+    #[must_use]
+    pub fn new(threshold: f32, ttl: Duration) -> Self {
+        Self {
+            threshold,
+            ttl,
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the events of the cached answer closest to the question, if one is similar enough, was given from the same generation of the index and hasn't expired.
+    #[must_use]
+    pub fn get(
+        &self,
+        question: &Question,
+        embedding: &[f32],
+        generation: &str,
+    ) -> Option<Vec<PartialResult>> {
+        let embedding = normalize(embedding.to_vec());
+        let question = without_query(question);
+        let entries = self.entries.read().ok()?;
+
+        entries
+            .get(&question.project_id)?
+            .iter()
+            .filter(|entry| {
+                entry.cached_at.elapsed() < self.ttl
+                    && entry.generation == generation
+                    && entry.question == question
+            })
+            .map(|entry| {
+                let similarity = entry
+                    .embedding
+                    .iter()
+                    .zip(&embedding)
+                    .map(|(a, b)| a * b)
+                    .sum::<f32>();
+
+                (similarity, entry)
+            })
+            .filter(|(similarity, _)| *similarity >= self.threshold)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, entry)| entry.events.clone())
+    }
+
+    /// Caches the events streamed in answer to a question, from the given generation of the index.
+    pub fn insert(
+        &self,
+        question: &Question,
+        embedding: Vec<f32>,
+        generation: String,
+        events: Vec<PartialResult>,
+    ) {
+        let Ok(mut entries) = self.entries.write() else {
+            return;
+        };
+
+        let project = entries.entry(question.project_id.clone()).or_default();
+        project.retain(|entry| entry.cached_at.elapsed() < self.ttl);
+        if project.len() >= MAX_ENTRIES_PER_PROJECT {
+            project.remove(0);
+        }
+
+        project.push(Entry {
+            events,
+            question: without_query(question),
+            generation,
+            embedding: normalize(embedding),
+            cached_at: Instant::now(),
+        });
+    }
+
+    /// Forgets every answer cached for a project, so re-indexed documentation is used right away.
+    pub fn invalidate(&self, project_id: &str) {
+        if let Ok(mut entries) = self.entries.write() {
+            entries.remove(project_id);
+        }
+    }
+}
+
+impl Default for AnswerCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_THRESHOLD, DEFAULT_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{budget::ContextBudget, ChatSettings, PromptTemplate, SearchOptions};
+
+    fn question(project_id: &str) -> Question {
+        Question {
+            project_id: project_id.to_string(),
+            project_name: "Clippy".to_string(),
+            query: "How do I embed docs?".to_string(),
+            history: Vec::new(),
+            settings: ChatSettings::default(),
+            search: SearchOptions::default(),
+            prompt: PromptTemplate::default(),
+            budget: ContextBudget::default(),
+        }
+    }
+
+    fn answer(text: &str) -> Vec<PartialResult> {
+        vec![PartialResult::PartialAnswer(text.to_string())]
+    }
+
+    #[test]
+    fn replays_answers_to_similar_questions() {
+        // Given
+        let cache = AnswerCache::new(0.9, Duration::from_secs(60));
+        cache.insert(
+            &question("docs"),
+            vec![1.0, 0.0],
+            "docs_1:3".to_string(),
+            answer("Run clippy embed."),
+        );
+
+        // Then
+        assert!(matches!(
+            cache.get(&question("docs"), &[2.0, 0.1], "docs_1:3").as_deref(),
+            Some([PartialResult::PartialAnswer(a)]) if a == "Run clippy embed."
+        ));
+        assert!(cache
+            .get(&question("docs"), &[0.5, 0.5], "docs_1:3")
+            .is_none());
+        assert!(cache
+            .get(&question("docs"), &[1.0, 0.0], "docs_2:3")
+            .is_none());
+        assert!(cache
+            .get(&question("other"), &[1.0, 0.0], "docs_1:3")
+            .is_none());
+        assert!(cache
+            .get(
+                &Question {
+                    settings: ChatSettings {
+                        temperature: 0.0,
+                        ..ChatSettings::default()
+                    },
+                    ..question("docs")
+                },
+                &[1.0, 0.0],
+                "docs_1:3"
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn expires_and_invalidates_answers() {
+        // Given
+        let expired = AnswerCache::new(0.9, Duration::ZERO);
+        expired.insert(
+            &question("docs"),
+            vec![1.0, 0.0],
+            "docs_1:1".to_string(),
+            answer("Old answer."),
+        );
+
+        let cache = AnswerCache::new(0.9, Duration::from_secs(60));
+        cache.insert(
+            &question("docs"),
+            vec![1.0, 0.0],
+            "docs_1:1".to_string(),
+            answer("Old answer."),
+        );
+
+        // When
+        cache.invalidate("docs");
+
+        // Then
+        assert!(expired
+            .get(&question("docs"), &[1.0, 0.0], "docs_1:1")
+            .is_none());
+        assert!(cache
+            .get(&question("docs"), &[1.0, 0.0], "docs_1:1")
+            .is_none());
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use std::{collections::VecDeque, fmt::Debug, pin::Pin, sync::Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+impl ChatMessage {
+    #[must_use]
+    pub fn new<S: Into<String>>(role: Role, content: S) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+}
+
+/// Settings used when prompting a chat model, configurable per project.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChatSettings {
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: Option<u16>,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            max_tokens: None,
+            temperature: 0.5,
+            model: "gpt-3.5-turbo".to_string(),
+        }
+    }
+}
+
+/// A stream of text deltas generated by a chat model.
+pub type TextStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+/// A model that generates answers from a conversation.
+#[async_trait]
+pub trait ChatModel: Debug + Send + Sync {
+    /// Prompts the model, returning a stream of text deltas.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the model could not be prompted.
+    async fn chat_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        settings: &ChatSettings,
+    ) -> Result<TextStream>;
+
+    /// Prompts the model, returning the full answer.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the model could not be prompted or the stream fails midway.
+    async fn chat(&self, messages: Vec<ChatMessage>, settings: &ChatSettings) -> Result<String> {
+        let answer = self
+            .chat_stream(messages, settings)
+            .await?
+            .try_collect::<String>()
+            .await?;
+
+        Ok(answer.trim().to_string())
+    }
+}
+
+/// A chat model that replies with pre-scripted answers, for testing.
+///
+/// Each call to the model consumes the next scripted reply, streaming it one delta at a time.
+#[derive(Debug, Default)]
+pub struct ScriptedChatModel {
+    replies: Mutex<VecDeque<Vec<String>>>,
+    requests: Mutex<Vec<Vec<ChatMessage>>>,
+}
+
+impl ScriptedChatModel {
+    /// Queues a reply, made of the given deltas.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if a previous call to the model panicked.
+    #[must_use]
+    pub fn reply<I, S>(self, deltas: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.replies
+            .lock()
+            .unwrap()
+            .push_back(deltas.into_iter().map(Into::into).collect());
+
+        self
+    }
+
+    /// Returns the messages the model has been prompted with so far.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if a previous call to the model panicked.
+    #[must_use]
+    pub fn requests(&self) -> Vec<Vec<ChatMessage>> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ChatModel for ScriptedChatModel {
+    async fn chat_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        _: &ChatSettings,
+    ) -> Result<TextStream> {
+        self.requests
+            .lock()
+            .map_err(|_| anyhow!("Scripted model lock poisoned"))?
+            .push(messages);
+
+        let deltas = self
+            .replies
+            .lock()
+            .map_err(|_| anyhow!("Scripted model lock poisoned"))?
+            .pop_front()
+            .ok_or_else(|| anyhow!("No scripted replies left"))?;
+
+        Ok(stream::iter(deltas).map(Ok).boxed())
+    }
+}
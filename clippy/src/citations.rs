@@ -0,0 +1,96 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::ops::Range;
+
+use crate::store::Payload;
+
+lazy_static! {
+    static ref MARKER_RE: Regex = Regex::new(r"\[(\d+(?:\s*,\s*\d+)*)\]").unwrap();
+}
+
+/// A source the answer cites, along with where it cites it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Citation {
+    /// The number the source was given in the prompt, starting at 1.
+    pub number: usize,
+    pub source: Payload,
+    /// The character ranges of the answer's markers citing the source, like `[2]` or `[1, 2]`.
+    pub spans: Vec<Range<usize>>,
+}
+
+/// Finds the `[n]` source markers in an answer, returning the sources it cites in order of first citation.
+///
+/// Markers referring to sources that don't exist are ignored, and so are markdown links like `[1](/path)`.
+#[must_use]
+pub fn parse(answer: &str, sources: &[Payload]) -> Vec<Citation> {
+    let mut citations: Vec<Citation> = Vec::new();
+
+    for marker in MARKER_RE.captures_iter(answer) {
+        let Some(whole) = marker.get(0) else {
+            continue;
+        };
+        if answer[whole.end()..].starts_with('(') {
+            continue;
+        }
+
+        let start = answer[..whole.start()].chars().count();
+        let span = start..start + whole.as_str().chars().count();
+
+        for number in marker[1]
+            .split(',')
+            .filter_map(|n| n.trim().parse::<usize>().ok())
+        {
+            let Some(source) = number.checked_sub(1).and_then(|i| sources.get(i)) else {
+                continue;
+            };
+
+            match citations.iter_mut().find(|c| c.number == number) {
+                Some(citation) if citation.spans.last() != Some(&span) => {
+                    citation.spans.push(span.clone());
+                }
+                Some(_) => {}
+                None => citations.push(Citation {
+                    number,
+                    source: source.clone(),
+                    spans: vec![span.clone()],
+                }),
+            }
+        }
+    }
+
+    citations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(path: &str) -> Payload {
+        Payload {
+            path: path.to_string(),
+            ..Payload::default()
+        }
+    }
+
+    #[test]
+    fn parses_cited_sources() {
+        // Given
+        let sources = [source("/install"), source("/embed"), source("/query")];
+        let answer = "Install the CLI [1], then run `clippy embed` [3] [1, 3]. See [the guide](/guide) or [7].";
+
+        // When
+        let citations = parse(answer, &sources);
+
+        // Then
+        assert_eq!(
+            citations
+                .iter()
+                .map(|c| (c.number, c.source.path.as_str(), c.spans.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, "/install", vec![16..19, 49..55]),
+                (3, "/query", vec![45..48, 49..55]),
+            ]
+        );
+    }
+}
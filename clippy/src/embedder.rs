@@ -0,0 +1,638 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::{stream, Future, StreamExt, TryStreamExt};
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    fs,
+    io::ErrorKind,
+    num::NonZeroUsize,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tracing::warn;
+
+use crate::{
+    index::{point_id, section_hash},
+    parser::Document,
+    store::{Payload, PointStruct},
+    tokens,
+};
+
+/// A model that turns text into embedding vectors.
+#[async_trait]
+pub trait Embedder: Debug + Send + Sync {
+    /// The name of the model used to generate the embeddings.
+    fn model(&self) -> &str;
+
+    /// The size of the vectors returned by this embedder.
+    fn dimensions(&self) -> usize;
+
+    /// Embeds a batch of strings, returning one vector per input (in the same order).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the underlying model fails to generate the embeddings.
+    async fn embed_batch(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>>;
+
+    /// Embeds a string into a vector.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the underlying model fails to generate the embedding.
+    async fn raw_embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed_batch(vec![text.to_string()])
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow!("Could not find embedding"))
+    }
+
+    /// Embeds a document into a vector of points, identified by the project they belong to and their content.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the underlying model fails to generate the embeddings.
+    async fn embed(&self, project: &str, document: &Document) -> Result<Vec<PointStruct>> {
+        let inputs = document
+            .sections
+            .iter()
+            .map(|s| {
+                format!(
+                    "{}{}",
+                    s.title.as_ref().map_or(String::new(), |t| format!("{t}: ")),
+                    s.content.replace('\n', " ")
+                )
+            })
+            .collect::<Vec<String>>();
+
+        let vectors = self.embed_batch(inputs).await?;
+
+        Ok(document
+            .sections
+            .iter()
+            .zip(vectors)
+            .map(|(section, vector)| PointStruct {
+                vector,
+                id: point_id(project, &document.path, &section_hash(document, section)),
+                payload: Payload {
+                    text: section.content.clone(),
+                    path: document.path.clone(),
+                    page_title: document.title.clone(),
+                    title: section.title.clone().unwrap_or_default(),
+                    metadata: document.metadata.clone(),
+                    tags: document.tags.clone(),
+                },
+            })
+            .collect())
+    }
+}
+
+/// Controls how inputs are split into embedding requests, and how many of them run at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// The number of inputs sent in a single request.
+    pub max_inputs: usize,
+    /// The number of tokens sent in a single request. Inputs longer than this get a request of their own.
+    pub max_tokens: usize,
+    /// The number of requests running at the same time.
+    pub concurrency: usize,
+    /// How many times a failed request is retried before giving up.
+    pub retries: u32,
+    /// How long to wait before the first retry. The delay doubles after every attempt.
+    pub retry_delay: Duration,
+}
+
+/// Groups consecutive inputs into batches that fit the limits of a single request.
+fn batch_ranges(inputs: &[String], options: &BatchOptions) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut batch_tokens = 0;
+
+    for (i, input) in inputs.iter().enumerate() {
+        let input_tokens = tokens::count(input);
+
+        if i > start
+            && (i - start >= options.max_inputs || batch_tokens + input_tokens > options.max_tokens)
+        {
+            ranges.push(start..i);
+            start = i;
+            batch_tokens = 0;
+        }
+
+        batch_tokens += input_tokens;
+    }
+
+    if start < inputs.len() {
+        ranges.push(start..inputs.len());
+    }
+
+    ranges
+}
+
+/// Embeds the inputs in batches, running a bounded number of requests at once and retrying the ones that fail.
+///
+/// # Errors
+///
+/// This function will return an error if a batch still fails after being retried, or doesn't return a vector per input.
+pub async fn embed_in_batches<F, Fut>(
+    inputs: Vec<String>,
+    options: &BatchOptions,
+    embed: F,
+) -> Result<Vec<Vec<f32>>>
+where
+    F: Fn(Vec<String>) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Vec<Vec<f32>>>> + Send,
+{
+    let embed = &embed;
+    let batches = batch_ranges(&inputs, options)
+        .into_iter()
+        .map(|range| inputs[range].to_vec())
+        .map(|batch| async move {
+            let mut delay = options.retry_delay;
+
+            for attempt in 0.. {
+                let result = embed(batch.clone()).await.and_then(|vectors| {
+                    if vectors.len() == batch.len() {
+                        Ok(vectors)
+                    } else {
+                        Err(anyhow!(
+                            "Expected {} embeddings, got {}",
+                            batch.len(),
+                            vectors.len()
+                        ))
+                    }
+                });
+
+                match result {
+                    Err(e) if attempt < options.retries => {
+                        warn!(
+                            "Failed to embed a batch of {} inputs, retrying: {e}",
+                            batch.len()
+                        );
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                    }
+                    result => return result,
+                }
+            }
+
+            unreachable!("Batches are retried a bounded number of times")
+        });
+
+    Ok(stream::iter(batches)
+        .buffered(options.concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// A deterministic embedder that hashes words into a fixed-size bag-of-words vector.
+///
+/// It needs no network access, which makes it useful for tests and offline environments.
+#[derive(Debug, Clone)]
+pub struct HashEmbedder {
+    dimensions: usize,
+}
+
+impl HashEmbedder {
+    #[must_use]
+    pub const fn new(dimensions: usize) -> Self {
+        Self { dimensions }
+    }
+
+    fn vectorize(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0; self.dimensions];
+
+        for word in text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+        {
+            let hash = fnv1a(word.to_lowercase().as_bytes());
+
+            #[allow(clippy::cast_possible_truncation)]
+            let index = (hash % self.dimensions as u64) as usize;
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+
+            vector[index] += sign;
+        }
+
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|v| *v /= norm);
+        }
+
+        vector
+    }
+}
+
+impl Default for HashEmbedder {
+    fn default() -> Self {
+        Self::new(1536)
+    }
+}
+
+#[async_trait]
+impl Embedder for HashEmbedder {
+    fn model(&self) -> &str {
+        "hashed-bag-of-words"
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    async fn embed_batch(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        Ok(inputs.iter().map(|text| self.vectorize(text)).collect())
+    }
+}
+
+/// How many vectors are kept in memory by default, before making room for new ones.
+const CACHE_CAPACITY: NonZeroUsize = match NonZeroUsize::new(10_000) {
+    Some(capacity) => capacity,
+    None => unreachable!(),
+};
+
+/// How often embeddings were served from the cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EmbeddingStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl EmbeddingStats {
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_rate(&self) -> f32 {
+        if self.hits + self.misses == 0 {
+            return 0.0;
+        }
+
+        self.hits as f32 / (self.hits + self.misses) as f32
+    }
+}
+
+/// Wraps an embedder, remembering the vectors of the texts it already embedded so identical text is only embedded once.
+///
+/// Vectors are keyed by a hash of the model name and the text, and can be persisted to disk so they survive between runs.
+#[derive(Debug)]
+pub struct CachedEmbedder {
+    inner: Arc<dyn Embedder>,
+    path: Option<PathBuf>,
+    vectors: Mutex<LruCache<String, Vec<f32>>>,
+    /// Whether vectors were embedded since the cache was loaded or last saved.
+    changed: AtomicBool,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl CachedEmbedder {
+    /// Creates an in-memory cache, which forgets the least recently used vectors once it holds too many.
+    #[must_use]
+    pub fn new(inner: Arc<dyn Embedder>) -> Self {
+        Self::with_capacity(inner, CACHE_CAPACITY)
+    }
+
+    /// Creates an in-memory cache holding at most `capacity` vectors.
+    #[must_use]
+    pub fn with_capacity(inner: Arc<dyn Embedder>, capacity: NonZeroUsize) -> Self {
+        Self {
+            inner,
+            path: None,
+            vectors: Mutex::new(LruCache::new(capacity)),
+            changed: AtomicBool::new(false),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Creates a cache persisted in the given directory, with a file per model. Call [`CachedEmbedder::save`] to write it.
+    ///
+    /// Like the in-memory cache, it only keeps the most recently used vectors, so the file doesn't grow forever.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache exists but could not be read or parsed.
+    pub fn persisted<P: AsRef<Path>>(inner: Arc<dyn Embedder>, directory: P) -> Result<Self> {
+        let path = directory
+            .as_ref()
+            .join(format!("{}.json", inner.model().replace('/', "_")));
+
+        // Vectors are stored from the least to the most recently used, so loading them keeps their order.
+        let vectors: Vec<(String, Vec<f32>)> = match fs::read_to_string(&path) {
+            Ok(vectors) => serde_json::from_str(&vectors)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut cache = LruCache::new(CACHE_CAPACITY);
+        for (key, vector) in vectors {
+            cache.put(key, vector);
+        }
+
+        Ok(Self {
+            path: Some(path),
+            vectors: Mutex::new(cache),
+            ..Self::new(inner)
+        })
+    }
+
+    /// Writes the cache to disk, if it is persisted and vectors were embedded since it was loaded or last saved.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache could not be written.
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        let vectors = serde_json::to_string(
+            &self
+                .vectors
+                .lock()
+                .map_err(|_| anyhow!("Embedding cache lock poisoned"))?
+                .iter()
+                .rev()
+                .collect::<Vec<_>>(),
+        )?;
+        fs::write(path, vectors)?;
+
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> EmbeddingStats {
+        EmbeddingStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn key(&self, text: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.inner.model());
+        hasher.update([0]);
+        hasher.update(text);
+
+        hex::encode(hasher.finalize())
+    }
+}
+
+#[async_trait]
+impl Embedder for CachedEmbedder {
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn dimensions(&self) -> usize {
+        self.inner.dimensions()
+    }
+
+    async fn embed_batch(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let keys = inputs.iter().map(|i| self.key(i)).collect::<Vec<_>>();
+        let mut vectors = {
+            let mut cached = self
+                .vectors
+                .lock()
+                .map_err(|_| anyhow!("Embedding cache lock poisoned"))?;
+
+            keys.iter()
+                .map(|key| cached.get(key).cloned())
+                .collect::<Vec<_>>()
+        };
+
+        // Identical texts in the same batch only need to be embedded once.
+        let mut missing: Vec<(String, String)> = Vec::new();
+        for ((key, input), vector) in keys.iter().zip(inputs).zip(&vectors) {
+            if vector.is_none() && !missing.iter().any(|(k, _)| k == key) {
+                missing.push((key.clone(), input));
+            }
+        }
+
+        self.hits
+            .fetch_add(keys.len() - missing.len(), Ordering::Relaxed);
+        self.misses.fetch_add(missing.len(), Ordering::Relaxed);
+
+        if !missing.is_empty() {
+            let (missing_keys, missing_inputs): (Vec<_>, Vec<_>) = missing.into_iter().unzip();
+            let embedded = missing_keys
+                .into_iter()
+                .zip(self.inner.embed_batch(missing_inputs).await?)
+                .collect::<HashMap<_, _>>();
+
+            for (key, vector) in keys.iter().zip(&mut vectors) {
+                if vector.is_none() {
+                    *vector = embedded.get(key).cloned();
+                }
+            }
+
+            let mut cached = self
+                .vectors
+                .lock()
+                .map_err(|_| anyhow!("Embedding cache lock poisoned"))?;
+            for (key, vector) in embedded {
+                cached.put(key, vector);
+            }
+            self.changed.store(true, Ordering::Relaxed);
+        }
+
+        vectors
+            .into_iter()
+            .map(|vector| vector.ok_or_else(|| anyhow!("Could not find embedding")))
+            .collect()
+    }
+}
+
+/// A stable 64-bit FNV-1a hash, so vectors don't change between builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_embeddings_are_deterministic() {
+        // Given
+        let embedder = HashEmbedder::new(64);
+
+        // When
+        let first = embedder.vectorize("The quick brown fox");
+        let second = embedder.vectorize("the QUICK brown, fox!");
+
+        // Then
+        assert_eq!(first.len(), 64);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn hash_embeddings_are_normalized() {
+        // When
+        let vector = HashEmbedder::new(64).vectorize("jumped over the lazy dog");
+
+        // Then
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < f32::EPSILON * 4.0);
+    }
+
+    #[tokio::test]
+    async fn caches_embeddings_by_content() {
+        // Given
+        let directory =
+            std::env::temp_dir().join(format!("clippy-embeddings-{}", std::process::id()));
+        let embedder =
+            CachedEmbedder::persisted(Arc::new(HashEmbedder::new(8)), &directory).unwrap();
+
+        // When
+        let first = embedder
+            .embed_batch(vec![
+                "Install".to_string(),
+                "Usage".to_string(),
+                "Install".to_string(),
+            ])
+            .await
+            .unwrap();
+        let second = embedder.raw_embed("Usage").await.unwrap();
+        embedder.save().unwrap();
+
+        let reloaded =
+            CachedEmbedder::persisted(Arc::new(HashEmbedder::new(8)), &directory).unwrap();
+        reloaded.raw_embed("Install").await.unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        // Then
+        assert_eq!(first[0], first[2]);
+        assert_eq!(first[1], second);
+        assert_eq!(embedder.stats(), EmbeddingStats { hits: 2, misses: 2 });
+        assert_eq!(reloaded.stats(), EmbeddingStats { hits: 1, misses: 0 });
+    }
+
+    #[tokio::test]
+    async fn only_saves_new_embeddings() {
+        // Given
+        let directory =
+            std::env::temp_dir().join(format!("clippy-unchanged-{}", std::process::id()));
+        let embedder =
+            CachedEmbedder::persisted(Arc::new(HashEmbedder::new(8)), &directory).unwrap();
+        embedder.raw_embed("Install").await.unwrap();
+        embedder.save().unwrap();
+
+        // When
+        let reloaded =
+            CachedEmbedder::persisted(Arc::new(HashEmbedder::new(8)), &directory).unwrap();
+        reloaded.raw_embed("Install").await.unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        reloaded.save().unwrap();
+
+        // Then
+        assert!(!directory.exists());
+    }
+
+    #[tokio::test]
+    async fn evicts_the_least_recently_used_vectors() {
+        // Given
+        let embedder = CachedEmbedder::with_capacity(
+            Arc::new(HashEmbedder::new(8)),
+            NonZeroUsize::new(2).unwrap(),
+        );
+        embedder
+            .embed_batch(vec!["Install".to_string(), "Usage".to_string()])
+            .await
+            .unwrap();
+        embedder.raw_embed("Install").await.unwrap();
+
+        // When
+        embedder.raw_embed("Deploy").await.unwrap();
+        embedder.raw_embed("Install").await.unwrap();
+        embedder.raw_embed("Usage").await.unwrap();
+
+        // Then
+        assert_eq!(embedder.stats(), EmbeddingStats { hits: 2, misses: 4 });
+    }
+
+    #[tokio::test]
+    async fn retries_failed_batches_only() {
+        // Given
+        let options = BatchOptions {
+            max_inputs: 2,
+            max_tokens: 100,
+            concurrency: 2,
+            retries: 1,
+            retry_delay: Duration::ZERO,
+        };
+        let inputs = ["one", "two", "three", "four", "five"].map(ToString::to_string);
+        let requests = std::sync::Mutex::new(Vec::new());
+
+        // When
+        let vectors = embed_in_batches(inputs.to_vec(), &options, |batch: Vec<String>| {
+            let mut requests = requests.lock().unwrap();
+            let attempt = requests.iter().filter(|b| **b == batch).count();
+            requests.push(batch.clone());
+
+            async move {
+                if batch[0] == "three" && attempt == 0 {
+                    return Err(anyhow!("Rate limited"));
+                }
+
+                Ok(batch
+                    .iter()
+                    .map(|b| vec![tokens::count(b) as f32])
+                    .collect())
+            }
+        })
+        .await
+        .unwrap();
+
+        // Then
+        assert_eq!(vectors.len(), 5);
+        assert_eq!(requests.lock().unwrap().len(), 4);
+        assert_eq!(
+            batch_ranges(
+                &inputs,
+                &BatchOptions {
+                    max_tokens: 2,
+                    ..options
+                }
+            ),
+            vec![0..2, 2..4, 4..5]
+        );
+        assert_eq!(
+            batch_ranges(
+                &inputs,
+                &BatchOptions {
+                    max_tokens: 1,
+                    ..options
+                }
+            ),
+            vec![0..1, 1..2, 2..3, 3..4, 4..5]
+        );
+    }
+
+    #[test]
+    fn empty_text_embeds_to_zero_vector() {
+        // When
+        let vector = HashEmbedder::new(8).vectorize("  ");
+
+        // Then
+        assert_eq!(vector, vec![0.0; 8]);
+    }
+}
@@ -0,0 +1,725 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::ErrorKind,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    parser::{Document, MarkdownSection},
+    Embedder, VectorStore,
+};
+
+/// A summary of the changes made to a collection while indexing.
+///
+/// A section whose content changed counts as updated, as long as the document also lost a section in exchange.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct IndexReport {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+/// The section hashes embedded for each document of a project, so re-indexing only has to embed what changed.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    documents: BTreeMap<String, Vec<String>>,
+}
+
+impl Manifest {
+    /// Loads a manifest from disk, or returns an empty one if it doesn't exist yet.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the manifest exists but could not be read or parsed.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        match fs::read_to_string(path.as_ref()) {
+            Ok(manifest) => Ok(serde_json::from_str(&manifest)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the manifest to disk.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the manifest could not be written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+
+    /// The number of sections embedded across every document.
+    #[must_use]
+    pub fn sections(&self) -> usize {
+        self.documents.values().map(Vec::len).sum()
+    }
+}
+
+/// Hashes everything that ends up in a section's payload: its title and content, and the title and metadata of its document.
+#[must_use]
+pub fn section_hash(document: &Document, section: &MarkdownSection) -> String {
+    let mut hasher = Sha256::new();
+    let mut field = |value: &str| {
+        hasher.update(value);
+        hasher.update([0]);
+    };
+
+    // Hash maps iterate in random order, so sort the metadata to keep the hash stable.
+    let mut metadata = document.metadata.iter().collect::<Vec<_>>();
+    metadata.sort();
+
+    field(&document.title);
+    for (key, value) in metadata {
+        field(key);
+        field(value);
+    }
+    for tag in &document.tags {
+        field(tag);
+    }
+    field(section.title.as_deref().unwrap_or_default());
+
+    hasher.update(&section.content);
+
+    hex::encode(hasher.finalize())
+}
+
+/// Derives a stable point id from the project, the document path and the section's content hash, so re-embedding the same section always produces the same point.
+#[must_use]
+pub fn point_id(project: &str, path: &str, hash: &str) -> String {
+    Uuid::new_v5(
+        &Uuid::NAMESPACE_URL,
+        format!("clippy://{project}{path}#{hash}").as_bytes(),
+    )
+    .to_string()
+}
+
+/// Embeds the sections of the given documents that aren't in the manifest yet, and removes the ones that no longer exist.
+///
+/// The manifest is updated as each document is indexed, so it stays in sync with the collection even if indexing fails midway.
+///
+/// # Errors
+///
+/// This function will return an error if the embedder or the vector store return an error.
+pub async fn index_documents(
+    embedder: &dyn Embedder,
+    store: &dyn VectorStore,
+    collection: &str,
+    manifest: &mut Manifest,
+    documents: &[Document],
+) -> Result<IndexReport> {
+    index_into(embedder, store, collection, collection, manifest, documents).await
+}
+
+/// Indexes the documents into a collection, deriving point ids from the project instead, so they stay the same whichever collection the project's alias points to.
+async fn index_into(
+    embedder: &dyn Embedder,
+    store: &dyn VectorStore,
+    project: &str,
+    collection: &str,
+    manifest: &mut Manifest,
+    documents: &[Document],
+) -> Result<IndexReport> {
+    let mut report = IndexReport::default();
+
+    let indexed = async {
+        reconcile(store, project, collection, manifest, &mut report).await?;
+        embed_documents(
+            embedder,
+            store,
+            project,
+            collection,
+            manifest,
+            documents,
+            &mut report,
+        )
+        .await?;
+
+        let paths = documents
+            .iter()
+            .map(|d| d.path.as_str())
+            .collect::<HashSet<_>>();
+        let removed = manifest
+            .documents
+            .keys()
+            .filter(|path| !paths.contains(path.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        remove_documents(store, project, collection, manifest, &removed, &mut report).await
+    }
+    .await;
+
+    // The manifest already records what was indexed before a failure, so it has to be persisted either way.
+    store.flush().await?;
+    indexed?;
+
+    info!(
+        "Indexed {collection}: {} added, {} updated, {} removed, {} unchanged.",
+        report.added, report.updated, report.removed, report.unchanged
+    );
+
+    Ok(report)
+}
+
+/// Brings the manifest back in sync with the collection when they disagree on the number of points, like when the manifest was lost or indexing failed before it was saved.
+///
+/// Points the manifest doesn't know about are deleted, and sections whose points are gone are forgotten so they get embedded again.
+async fn reconcile(
+    store: &dyn VectorStore,
+    project: &str,
+    collection: &str,
+    manifest: &mut Manifest,
+    report: &mut IndexReport,
+) -> Result<()> {
+    let expected = manifest
+        .documents
+        .iter()
+        .flat_map(|(path, hashes)| hashes.iter().map(move |hash| point_id(project, path, hash)))
+        .collect::<HashSet<_>>();
+
+    if expected.len() == store.collection_info(collection).await?.points {
+        return Ok(());
+    }
+
+    let ids = store
+        .ids(collection)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let stale = ids
+        .iter()
+        .filter(|id| !expected.contains(*id))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if !stale.is_empty() {
+        warn!(
+            "Removing {} points of {collection} missing from its manifest.",
+            stale.len()
+        );
+        store.delete(collection, &stale).await?;
+        report.removed += stale.len();
+    }
+
+    for (path, hashes) in &mut manifest.documents {
+        hashes.retain(|hash| ids.contains(&point_id(project, path, hash)));
+    }
+    manifest.documents.retain(|_, hashes| !hashes.is_empty());
+
+    Ok(())
+}
+
+/// Re-indexes only the given documents, and removes the ones at the given paths, leaving the rest of the collection untouched.
+///
+/// Paths that were never indexed are ignored, so callers don't have to know which of the files they removed made it into the index.
+///
+/// # Errors
+///
+/// This function will return an error if the embedder or the vector store return an error.
+pub async fn update_documents(
+    embedder: &dyn Embedder,
+    store: &dyn VectorStore,
+    collection: &str,
+    manifest: &mut Manifest,
+    documents: &[Document],
+    removed: &[String],
+) -> Result<IndexReport> {
+    let mut report = IndexReport::default();
+
+    let updated = async {
+        embed_documents(
+            embedder,
+            store,
+            collection,
+            collection,
+            manifest,
+            documents,
+            &mut report,
+        )
+        .await?;
+        remove_documents(
+            store,
+            collection,
+            collection,
+            manifest,
+            removed,
+            &mut report,
+        )
+        .await
+    }
+    .await;
+
+    store.flush().await?;
+    updated?;
+
+    info!(
+        "Updated {collection}: {} added, {} updated, {} removed, {} unchanged.",
+        report.added, report.updated, report.removed, report.unchanged
+    );
+
+    Ok(report)
+}
+
+/// Embeds the sections of each document that changed since the manifest was last updated, and deletes the ones it lost.
+async fn embed_documents(
+    embedder: &dyn Embedder,
+    store: &dyn VectorStore,
+    project: &str,
+    collection: &str,
+    manifest: &mut Manifest,
+    documents: &[Document],
+    report: &mut IndexReport,
+) -> Result<()> {
+    for document in documents {
+        let hashes = document
+            .sections
+            .iter()
+            .map(|section| section_hash(document, section))
+            .collect::<Vec<_>>();
+        let previous = manifest
+            .documents
+            .get(&document.path)
+            .cloned()
+            .unwrap_or_default();
+
+        let changed = document
+            .sections
+            .iter()
+            .zip(&hashes)
+            .filter(|(_, hash)| !previous.contains(*hash))
+            .map(|(section, _)| section.clone())
+            .collect::<Vec<_>>();
+        let stale = previous
+            .iter()
+            .filter(|hash| !hashes.contains(*hash))
+            .map(|hash| point_id(project, &document.path, hash))
+            .collect::<Vec<_>>();
+
+        let updated = changed.len().min(stale.len());
+        report.updated += updated;
+        report.added += changed.len() - updated;
+        report.removed += stale.len() - updated;
+        report.unchanged += hashes.len() - changed.len();
+
+        if !changed.is_empty() {
+            let points = embedder
+                .embed(
+                    project,
+                    &Document {
+                        sections: changed,
+                        ..document.clone()
+                    },
+                )
+                .await?;
+
+            store.upsert(collection, &points).await?;
+        }
+
+        if !stale.is_empty() {
+            store.delete(collection, &stale).await?;
+        }
+
+        manifest.documents.insert(document.path.clone(), hashes);
+    }
+
+    Ok(())
+}
+
+/// Deletes every section of the documents at the given paths.
+async fn remove_documents(
+    store: &dyn VectorStore,
+    project: &str,
+    collection: &str,
+    manifest: &mut Manifest,
+    paths: &[String],
+    report: &mut IndexReport,
+) -> Result<()> {
+    for path in paths {
+        let Some(hashes) = manifest.documents.get(path) else {
+            continue;
+        };
+        let stale = hashes
+            .iter()
+            .map(|hash| point_id(project, path, hash))
+            .collect::<Vec<_>>();
+
+        store.delete(collection, &stale).await?;
+
+        report.removed += stale.len();
+        manifest.documents.remove(path);
+    }
+
+    Ok(())
+}
+
+/// Indexes the documents into a fresh collection, then atomically points the alias to it, so searches never see a half-built index.
+///
+/// The collection the alias pointed to before is deleted, and the manifest is replaced with the fresh collection's once the alias has been swapped.
+///
+/// # Errors
+///
+/// This function will return an error if the embedder or the vector store return an error. The live collection is left untouched if indexing fails.
+pub async fn reindex(
+    embedder: &dyn Embedder,
+    store: &dyn VectorStore,
+    alias: &str,
+    manifest: &mut Manifest,
+    documents: &[Document],
+) -> Result<IndexReport> {
+    let collection = format!(
+        "{alias}_{}",
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos()
+    );
+    store
+        .create_collection(&collection, embedder.dimensions())
+        .await?;
+
+    let mut fresh = Manifest::default();
+    let report = match index_into(embedder, store, alias, &collection, &mut fresh, documents).await
+    {
+        Ok(report) => report,
+        Err(err) => {
+            store.delete_collection(&collection).await.ok();
+            return Err(err);
+        }
+    };
+
+    let previous = store.alias(alias).await?;
+    if previous.is_none() && store.collection_exists(alias).await? {
+        // Indexes built before aliases were introduced are collections named after the alias, which has to go before the alias can take its name.
+        warn!("Replacing collection {alias} with an alias, it will be unavailable until the alias is created.");
+        store.delete_collection(alias).await?;
+    }
+
+    store.set_alias(alias, &collection).await?;
+    *manifest = fresh;
+
+    if let Some(previous) = previous.filter(|previous| *previous != collection) {
+        store.delete_collection(&previous).await?;
+    }
+
+    info!("Swapped {alias} to {collection}.");
+
+    Ok(report)
+}
+
+/// Deletes an index along with the collection its alias points to.
+///
+/// # Errors
+///
+/// This function will return an error if the vector store returns an error.
+pub async fn drop_index(store: &dyn VectorStore, alias: &str) -> Result<()> {
+    match store.alias(alias).await? {
+        Some(collection) => {
+            store.delete_alias(alias).await?;
+            store.delete_collection(&collection).await
+        }
+        None => store.delete_collection(alias).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HashEmbedder, LocalStore};
+    use std::collections::HashMap;
+
+    fn document(path: &str, sections: &[&str]) -> Document {
+        Document {
+            path: path.to_string(),
+            title: "Guide".to_string(),
+            description: None,
+            metadata: HashMap::new(),
+            tags: Vec::new(),
+            sections: sections
+                .iter()
+                .map(|content| MarkdownSection {
+                    title: None,
+                    content: (*content).to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn point_ids_are_stable() {
+        let hash = section_hash(
+            &document("/install", &[]),
+            &MarkdownSection {
+                title: Some("Install".to_string()),
+                content: "Run the installer".to_string(),
+            },
+        );
+
+        assert_eq!(
+            point_id("docs", "/install", &hash),
+            point_id("docs", "/install", &hash)
+        );
+        assert_ne!(
+            point_id("docs", "/install", &hash),
+            point_id("docs", "/setup", &hash)
+        );
+        assert_ne!(
+            point_id("docs", "/install", &hash),
+            point_id("other", "/install", &hash)
+        );
+    }
+
+    #[test]
+    fn hashes_cover_document_metadata() {
+        // Given
+        let v1 = Document {
+            metadata: HashMap::from([("version".to_string(), "v1".to_string())]),
+            ..document("/install", &["Run the installer"])
+        };
+        let v2 = Document {
+            metadata: HashMap::from([("version".to_string(), "v2".to_string())]),
+            ..v1.clone()
+        };
+
+        // Then
+        assert_ne!(
+            section_hash(&v1, &v1.sections[0]),
+            section_hash(&v2, &v2.sections[0])
+        );
+    }
+
+    #[tokio::test]
+    async fn only_embeds_changed_sections() {
+        // Given
+        let embedder = HashEmbedder::new(16);
+        let store = LocalStore::default();
+        let mut manifest = Manifest::default();
+        store.create_collection("docs", 16).await.unwrap();
+
+        index_documents(
+            &embedder,
+            &store,
+            "docs",
+            &mut manifest,
+            &[document("/a", &["one", "two", "three"])],
+        )
+        .await
+        .unwrap();
+
+        // When
+        let report = index_documents(
+            &embedder,
+            &store,
+            "docs",
+            &mut manifest,
+            &[document("/a", &["one", "two, updated", "three", "four"])],
+        )
+        .await
+        .unwrap();
+
+        // Then
+        assert_eq!(
+            report,
+            IndexReport {
+                added: 1,
+                updated: 1,
+                removed: 0,
+                unchanged: 2
+            }
+        );
+        assert_eq!(store.ids("docs").await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn updates_only_the_given_documents() {
+        // Given
+        let embedder = HashEmbedder::new(16);
+        let store = LocalStore::default();
+        let mut manifest = Manifest::default();
+        store.create_collection("docs", 16).await.unwrap();
+
+        index_documents(
+            &embedder,
+            &store,
+            "docs",
+            &mut manifest,
+            &[
+                document("/a", &["one"]),
+                document("/b", &["two"]),
+                document("/c", &["three"]),
+            ],
+        )
+        .await
+        .unwrap();
+
+        // When
+        let report = update_documents(
+            &embedder,
+            &store,
+            "docs",
+            &mut manifest,
+            &[document("/a", &["one, updated"])],
+            &["/b".to_string(), "/never-indexed".to_string()],
+        )
+        .await
+        .unwrap();
+
+        // Then
+        assert_eq!(
+            report,
+            IndexReport {
+                added: 0,
+                updated: 1,
+                removed: 1,
+                unchanged: 0
+            }
+        );
+        assert_eq!(
+            manifest.documents.keys().collect::<Vec<_>>(),
+            vec!["/a", "/c"]
+        );
+        assert_eq!(store.ids("docs").await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn reindexes_into_a_fresh_collection() {
+        // Given
+        let embedder = HashEmbedder::new(16);
+        let store = LocalStore::default();
+        let mut manifest = Manifest::default();
+        store.create_collection("docs", 16).await.unwrap();
+
+        reindex(
+            &embedder,
+            &store,
+            "docs",
+            &mut manifest,
+            &[document("/a", &["one", "two"])],
+        )
+        .await
+        .unwrap();
+        let first = store.alias("docs").await.unwrap().unwrap();
+
+        // When
+        reindex(
+            &embedder,
+            &store,
+            "docs",
+            &mut manifest,
+            &[document("/a", &["one"])],
+        )
+        .await
+        .unwrap();
+
+        // Then
+        let second = store.alias("docs").await.unwrap().unwrap();
+        assert_ne!(first, second);
+        assert!(!store.collection_exists(&first).await.unwrap());
+        assert_eq!(store.collection_info("docs").await.unwrap().points, 1);
+
+        // When
+        let report = index_documents(
+            &embedder,
+            &store,
+            "docs",
+            &mut manifest,
+            &[document("/a", &["two"])],
+        )
+        .await
+        .unwrap();
+
+        // Then
+        assert_eq!(report.updated, 1);
+        assert_eq!(store.ids("docs").await.unwrap().len(), 1);
+
+        // When
+        drop_index(&store, "docs").await.unwrap();
+
+        // Then
+        assert!(store.alias("docs").await.unwrap().is_none());
+        assert!(!store.collection_exists(&second).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn reconciles_a_lost_manifest() {
+        // Given
+        let embedder = HashEmbedder::new(16);
+        let store = LocalStore::default();
+        store.create_collection("docs", 16).await.unwrap();
+
+        index_documents(
+            &embedder,
+            &store,
+            "docs",
+            &mut Manifest::default(),
+            &[document("/a", &["one"]), document("/b", &["two", "three"])],
+        )
+        .await
+        .unwrap();
+
+        // When
+        let mut manifest = Manifest::default();
+        let report = index_documents(
+            &embedder,
+            &store,
+            "docs",
+            &mut manifest,
+            &[document("/a", &["one"])],
+        )
+        .await
+        .unwrap();
+
+        // Then
+        assert_eq!(report.removed, 3);
+        assert_eq!(report.added, 1);
+        assert_eq!(manifest.sections(), 1);
+        assert_eq!(store.ids("docs").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn removes_sections_of_deleted_documents() {
+        // Given
+        let embedder = HashEmbedder::new(16);
+        let store = LocalStore::default();
+        let mut manifest = Manifest::default();
+        store.create_collection("docs", 16).await.unwrap();
+
+        index_documents(
+            &embedder,
+            &store,
+            "docs",
+            &mut manifest,
+            &[document("/a", &["one"]), document("/b", &["two", "three"])],
+        )
+        .await
+        .unwrap();
+
+        // When
+        let report = index_documents(
+            &embedder,
+            &store,
+            "docs",
+            &mut manifest,
+            &[document("/a", &["one"])],
+        )
+        .await
+        .unwrap();
+
+        // Then
+        assert_eq!(
+            report,
+            IndexReport {
+                added: 0,
+                updated: 0,
+                removed: 2,
+                unchanged: 1
+            }
+        );
+        assert_eq!(store.ids("docs").await.unwrap().len(), 1);
+    }
+}
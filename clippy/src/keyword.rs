@@ -0,0 +1,371 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::{
+    store::{Filter, PointResult, VectorStore},
+    Payload,
+};
+
+/// Controls how quickly the score of a term saturates as it repeats.
+const K1: f32 = 1.2;
+/// Controls how much longer sections are penalised.
+const B: f32 = 0.75;
+
+/// Splits text into lowercase terms.
+///
+/// Identifiers like `search_project` or `--store-path` are kept whole, and also split into their parts.
+#[must_use]
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+        .map(|word| word.trim_matches(|c| c == '_' || c == '-'))
+        .filter(|word| !word.is_empty())
+        .flat_map(|word| {
+            let word = word.to_lowercase();
+            let mut terms = word
+                .split(['_', '-'])
+                .filter(|part| !part.is_empty() && *part != word)
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+
+            terms.push(word);
+            terms
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Section {
+    id: String,
+    payload: Payload,
+    length: f32,
+    terms: HashMap<String, f32>,
+}
+
+/// An in-memory BM25 index over the text and titles of a collection's sections.
+#[derive(Debug, Default)]
+pub struct KeywordIndex {
+    sections: Vec<Section>,
+    document_frequency: HashMap<String, f32>,
+    average_length: f32,
+}
+
+impl KeywordIndex {
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new<I: IntoIterator<Item = (String, Payload)>>(points: I) -> Self {
+        let mut document_frequency = HashMap::new();
+
+        let sections = points
+            .into_iter()
+            .map(|(id, payload)| {
+                let terms = tokenize(&format!(
+                    "{} {} {}",
+                    payload.page_title, payload.title, payload.text
+                ));
+
+                let mut frequencies = HashMap::new();
+                for term in &terms {
+                    *frequencies.entry(term.clone()).or_insert(0.0) += 1.0;
+                }
+
+                for term in frequencies.keys() {
+                    *document_frequency.entry(term.clone()).or_insert(0.0) += 1.0;
+                }
+
+                Section {
+                    id,
+                    payload,
+                    length: terms.len() as f32,
+                    terms: frequencies,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let average_length =
+            sections.iter().map(|s| s.length).sum::<f32>() / sections.len().max(1) as f32;
+
+        Self {
+            sections,
+            document_frequency,
+            average_length,
+        }
+    }
+
+    /// Returns the `count` sections matching the filter that best match the query, scored with BM25.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn search(&self, query: &str, count: usize, filter: &Filter) -> Vec<PointResult> {
+        let total = self.sections.len() as f32;
+        let idf = tokenize(query)
+            .into_iter()
+            .filter_map(|term| {
+                let frequency = *self.document_frequency.get(&term)?;
+                Some((
+                    term,
+                    ((total - frequency + 0.5) / (frequency + 0.5)).ln_1p(),
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = self
+            .sections
+            .iter()
+            .filter(|section| filter.matches(&section.payload))
+            .filter_map(|section| {
+                let normalization =
+                    K1 * (1.0 - B + B * section.length / self.average_length.max(1.0));
+
+                let score = idf
+                    .iter()
+                    .filter_map(|(term, idf)| {
+                        let frequency = section.terms.get(term)?;
+                        Some(idf * frequency * (K1 + 1.0) / (frequency + normalization))
+                    })
+                    .sum::<f32>();
+
+                (score > 0.0).then(|| PointResult {
+                    score,
+                    id: section.id.clone(),
+                    payload: section.payload.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(count);
+        results
+    }
+}
+
+/// Keyword indexes built from the contents of the vector store, cached per collection.
+///
+/// Indexes are rebuilt once they are older than the configured time to live, so re-indexed documentation eventually shows up in keyword results.
+/// Only one search rebuilds a collection's index at a time, while the others keep using the previous one.
+#[derive(Debug)]
+pub struct KeywordIndexes {
+    ttl: Duration,
+    indexes: RwLock<HashMap<String, Cached>>,
+    /// Held while the index of a collection is being built, so concurrent searches don't all read the whole collection.
+    builds: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+#[derive(Debug, Clone)]
+struct Cached {
+    index: Arc<KeywordIndex>,
+    built_at: Instant,
+    invalidated: bool,
+}
+
+impl KeywordIndexes {
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            indexes: RwLock::new(HashMap::new()),
+            builds: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the keyword index for a collection, building it from the store if it isn't cached or has expired.
+    ///
+    /// An expired index is still returned while another search is rebuilding it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the contents of the collection could not be read.
+    pub async fn get(
+        &self,
+        store: &dyn VectorStore,
+        collection: &str,
+    ) -> Result<Arc<KeywordIndex>> {
+        let cached = self.cached(collection)?;
+        if let Some(cached) = cached.as_ref().filter(|cached| self.is_fresh(cached)) {
+            return Ok(cached.index.clone());
+        }
+
+        let build = self
+            .builds
+            .lock()
+            .map_err(|_| anyhow!("Keyword index lock poisoned"))?
+            .entry(collection.to_string())
+            .or_default()
+            .clone();
+
+        let _guard = match cached {
+            Some(cached) => match build.try_lock_owned() {
+                Ok(guard) => guard,
+                Err(_) => return Ok(cached.index),
+            },
+            None => build.lock_owned().await,
+        };
+
+        // Another search may have built the index while this one was waiting for the lock.
+        if let Some(cached) = self
+            .cached(collection)?
+            .filter(|cached| self.is_fresh(cached))
+        {
+            return Ok(cached.index);
+        }
+
+        let index = Arc::new(KeywordIndex::new(store.payloads(collection).await?));
+
+        self.indexes
+            .write()
+            .map_err(|_| anyhow!("Keyword index lock poisoned"))?
+            .insert(
+                collection.to_string(),
+                Cached {
+                    index: index.clone(),
+                    built_at: Instant::now(),
+                    invalidated: false,
+                },
+            );
+
+        Ok(index)
+    }
+
+    /// Marks the cached index for a collection as outdated, so it gets rebuilt on the next search.
+    pub fn invalidate(&self, collection: &str) {
+        if let Ok(mut indexes) = self.indexes.write() {
+            if let Some(cached) = indexes.get_mut(collection) {
+                cached.invalidated = true;
+            }
+        }
+    }
+
+    /// Drops the cached index for a collection that no longer exists.
+    pub fn remove(&self, collection: &str) {
+        if let Ok(mut indexes) = self.indexes.write() {
+            indexes.remove(collection);
+        }
+
+        if let Ok(mut builds) = self.builds.lock() {
+            builds.remove(collection);
+        }
+    }
+
+    fn cached(&self, collection: &str) -> Result<Option<Cached>> {
+        Ok(self
+            .indexes
+            .read()
+            .map_err(|_| anyhow!("Keyword index lock poisoned"))?
+            .get(collection)
+            .cloned())
+    }
+
+    fn is_fresh(&self, cached: &Cached) -> bool {
+        !cached.invalidated && cached.built_at.elapsed() < self.ttl
+    }
+}
+
+impl Default for KeywordIndexes {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(5 * 60))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::PointStruct, LocalStore};
+
+    fn payload(title: &str, text: &str) -> Payload {
+        Payload {
+            title: title.to_string(),
+            text: text.to_string(),
+            path: format!("/{}", title.to_lowercase()),
+            page_title: "CLI".to_string(),
+            ..Payload::default()
+        }
+    }
+
+    #[test]
+    fn tokenizes_identifiers() {
+        assert_eq!(
+            tokenize("Pass --store-path to `search_project`."),
+            vec![
+                "pass",
+                "store",
+                "path",
+                "store-path",
+                "to",
+                "search",
+                "project",
+                "search_project"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_the_stale_index_while_rebuilding() {
+        // Given
+        let store = LocalStore::default();
+        let indexes = KeywordIndexes::default();
+        store.create_collection("docs", 1).await.unwrap();
+        let point = |id: &str, text: &str| PointStruct {
+            id: id.to_string(),
+            vector: vec![1.0],
+            payload: payload("Guide", text),
+        };
+        store
+            .upsert("docs", &[point("1", "install")])
+            .await
+            .unwrap();
+        indexes.get(&store, "docs").await.unwrap();
+        store
+            .upsert("docs", &[point("2", "upgrade")])
+            .await
+            .unwrap();
+        indexes.invalidate("docs");
+
+        // When
+        let build = indexes.builds.lock().unwrap()["docs"].clone();
+        let guard = build.try_lock().unwrap();
+        let stale = indexes.get(&store, "docs").await.unwrap();
+        drop(guard);
+        let rebuilt = indexes.get(&store, "docs").await.unwrap();
+
+        // Then
+        assert!(stale.search("upgrade", 1, &Filter::default()).is_empty());
+        assert_eq!(rebuilt.search("upgrade", 1, &Filter::default()).len(), 1);
+    }
+
+    #[test]
+    fn ranks_exact_identifiers_first() {
+        // Given
+        let index = KeywordIndex::new([
+            (
+                "1".to_string(),
+                payload(
+                    "Storage",
+                    "Documents are stored in a local store by default.",
+                ),
+            ),
+            (
+                "2".to_string(),
+                payload(
+                    "Flags",
+                    "Pass --store-path to change where the store is persisted.",
+                ),
+            ),
+            (
+                "3".to_string(),
+                payload("Querying", "Ask questions about your documentation."),
+            ),
+        ]);
+
+        // When
+        let results = index.search("--store-path", 3, &Filter::default());
+
+        // Then
+        assert_eq!(
+            results.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(),
+            vec!["2", "1"]
+        );
+    }
+}
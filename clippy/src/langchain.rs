@@ -1,48 +1,159 @@
-use crate::qdrant::PointResult;
-use async_openai::types::{ChatCompletionRequestMessage, Role};
+use crate::{
+    chat::{ChatMessage, Role},
+    prompt::{PromptTemplate, PromptValues},
+    store::PointResult,
+    tokens,
+};
 use indoc::formatdoc;
 use std::fmt::Display;
 
+#[derive(Debug, Clone)]
 pub struct Context {
-    path: String,
-    content: String,
+    pub(crate) title: String,
+    pub(crate) path: String,
+    pub(crate) content: String,
 }
 
 impl Display for Context {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Path: {}\nContent:{}", self.path, self.content)
+        write!(
+            f,
+            "Title: {}\nPath: {}\nContent:{}",
+            self.title, self.path, self.content
+        )
     }
 }
 
 impl From<&PointResult> for Context {
     fn from(point: &PointResult) -> Self {
         Self {
+            title: point.payload.page_title.clone(),
             path: point.payload.path.clone(),
             content: point.payload.text.clone(),
         }
     }
 }
 
+/// Tells the model how to cite sources, so citations can be parsed out of the answer whatever the project's prompt says.
+const CITATION_INSTRUCTIONS: &str = "Cite the sections you use by adding their number in square brackets right after the sentence relying on them, like [1] or [1, 3]. Do not write links to the sections.";
+
+/// Builds the prompt used to answer a question about a project, following up on the given conversation history.
+#[must_use]
+pub fn build_messages(
+    prompt: &PromptTemplate,
+    project: &str,
+    query: &str,
+    sources: &[Context],
+    history: &[ChatMessage],
+) -> Vec<ChatMessage> {
+    let sources = sources
+        .iter()
+        .enumerate()
+        .map(|(i, source)| format!("[{}] {source}", i + 1))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let system = prompt.render(&PromptValues {
+        project,
+        question: query,
+        sources: &sources,
+    });
+
+    let mut messages = vec![ChatMessage::new(
+        Role::System,
+        format!("{system}\n\n{CITATION_INSTRUCTIONS}"),
+    )];
+
+    messages.extend_from_slice(history);
+    messages.push(ChatMessage::new(Role::User, query));
+    messages
+}
+
+/// Builds a prompt asking to rephrase a follow-up question so it can be understood (and searched for) without the conversation.
 #[must_use]
-pub fn build_messages(query: &str, sources: &[Context]) -> Vec<ChatCompletionRequestMessage> {
+pub fn build_condense_messages(query: &str, history: &[ChatMessage]) -> Vec<ChatMessage> {
+    let conversation = history
+        .iter()
+        .map(|message| match message.role {
+            Role::Assistant => format!("Assistant: {}", message.content),
+            Role::User | Role::System => format!("User: {}", message.content),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
     vec![
-        ChatCompletionRequestMessage {
-            name: None,
-            role: Role::System,
-            content: formatdoc!(
-                "You are a very enthusiastic company representative who loves to help people! Given the following sections from the documentation, give a comprehensive answer to the user's question, providing inline references in `[page title](path)` format (when relevant).
-                If you are unsure or the question doesn't relate to the project, say \"Sorry, I am not sure how to answer that.\"
-
-                Documentation:
-                ---
-                {}",
-                sources.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
-            )
-        },
-        ChatCompletionRequestMessage {
-            name: None,
-            role: Role::User,
-            content: query.to_string()
-        }
+        ChatMessage::new(
+            Role::System,
+            "Given the following conversation and a follow-up question, rephrase the follow-up question to be a standalone question, in its original language. Reply with the question only.",
+        ),
+        ChatMessage::new(
+            Role::User,
+            formatdoc!(
+                "Conversation:
+                {conversation}
+
+                Follow-up question: {query}"
+            ),
+        ),
     ]
 }
+
+/// Keeps the most recent messages of a conversation that fit in the given number of tokens.
+#[must_use]
+pub fn trim_history(history: &[ChatMessage], max_tokens: usize) -> &[ChatMessage] {
+    let mut budget = max_tokens;
+    let mut start = history.len();
+
+    for (i, message) in history.iter().enumerate().rev() {
+        let tokens = tokens::count(&message.content);
+        if tokens > budget {
+            break;
+        }
+
+        budget -= tokens;
+        start = i;
+    }
+
+    &history[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_messages_from_project_template() {
+        // Given
+        let prompt =
+            PromptTemplate::parse("Answer as the {{ project }} pirate.\n{{ sources }}").unwrap();
+        let sources = [Context {
+            title: "CLI".to_string(),
+            path: "/install".to_string(),
+            content: " Install with cargo.".to_string(),
+        }];
+
+        // When
+        let messages = build_messages(&prompt, "Clippy", "How do I install it?", &sources, &[]);
+
+        // Then
+        assert!(messages[0].content.starts_with(
+            "Answer as the Clippy pirate.\n[1] Title: CLI\nPath: /install\nContent: Install with cargo.\n\nCite the sections"
+        ));
+        assert_eq!(messages[1].content, "How do I install it?");
+    }
+
+    #[test]
+    fn trims_history_to_the_most_recent_messages() {
+        // Given
+        let history = vec![
+            ChatMessage::new(Role::User, "How do I install the CLI?"),
+            ChatMessage::new(Role::Assistant, "Run cargo install clippy."),
+            ChatMessage::new(Role::User, "And on Windows?"),
+        ];
+
+        // Then
+        assert_eq!(trim_history(&history, 100), &history[..]);
+        assert_eq!(trim_history(&history, 12), &history[1..]);
+        assert!(trim_history(&history, 2).is_empty());
+    }
+}
@@ -1,32 +1,32 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
 
+pub mod budget;
+pub mod cache;
+pub mod chat;
+pub mod citations;
+mod embedder;
+pub mod index;
+pub mod keyword;
 mod langchain;
 pub mod openai;
 mod parser;
+pub mod prompt;
 mod qdrant;
+pub mod rerank;
+pub mod search;
+pub mod store;
 pub mod stream;
+pub mod tokens;
+pub mod train;
 
+pub use chat::{ChatModel, ChatSettings};
+pub use embedder::{CachedEmbedder, Embedder, EmbeddingStats, HashEmbedder};
+pub use index::{drop_index, index_documents, reindex, update_documents, IndexReport, Manifest};
 pub use langchain::{build_messages, Context};
 pub use openai::OpenAI;
-pub use parser::{into_document, Document};
-pub use qdrant::{Payload, Qdrant};
-
-use anyhow::Result;
-use qdrant::PointResult;
-
-/// Searches a project's documentation.
-///
-/// # Errors
-///
-/// This function will panic if the `Qdrant` or the `OpenAI` APIs return an error.
-pub async fn search_project(
-    project_id: &str,
-    query: &str,
-    count: usize,
-) -> Result<Vec<PointResult>> {
-    let client = OpenAI::new();
-    let qdrant = Qdrant::new().collection(project_id);
-
-    let query_points = client.raw_embed(query).await?;
-    qdrant.query(query_points, count).await
-}
+pub use parser::{into_document, ChunkConfig, Document};
+pub use prompt::PromptTemplate;
+pub use qdrant::{Qdrant, UpsertOptions};
+pub use rerank::{LexicalReranker, LlmReranker, Reranker};
+pub use search::{Retriever, SearchOptions};
+pub use store::{CollectionInfo, LocalStore, Payload, VectorStore};
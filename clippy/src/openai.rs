@@ -1,22 +1,35 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use async_openai::{
     types::{
-        ChatCompletionRequestMessage, ChatCompletionResponseStream,
-        CreateChatCompletionRequestArgs, CreateEmbeddingRequestArgs,
+        ChatCompletionRequestMessage, CreateChatCompletionRequestArgs, CreateEmbeddingRequestArgs,
+        Role as OpenAIRole,
     },
     Client,
 };
+use async_trait::async_trait;
 use backoff::ExponentialBackoffBuilder;
-use futures::future;
+use futures::StreamExt;
 use std::{sync::Arc, time::Duration};
 use tracing::info;
-use uuid::Uuid;
 
 use crate::{
-    parser::Document,
-    qdrant::{Payload, PointStruct},
+    chat::{ChatMessage, ChatModel, ChatSettings, Role, TextStream},
+    embedder::{embed_in_batches, BatchOptions, Embedder},
+    tokens,
 };
 
+const EMBEDDING_MODEL: &str = "text-embedding-ada-002";
+const EMBEDDING_SIZE: usize = 1536;
+const EMBEDDING_MAX_TOKENS: usize = 8191;
+const EMBEDDING_BATCHES: BatchOptions = BatchOptions {
+    max_inputs: 100,
+    max_tokens: 50_000,
+    concurrency: 4,
+    retries: 3,
+    retry_delay: Duration::from_secs(1),
+};
+
+#[derive(Debug)]
 pub struct OpenAI {
     client: Arc<Client>,
 }
@@ -38,152 +51,109 @@ impl OpenAI {
             client: Arc::new(Client::new().with_backoff(backoff)),
         }
     }
+}
 
-    /// Embeds a document into a vector of points.
-    ///
-    /// # Errors
-    ///
-    /// This function will panic if the Embeddings API returns an error.
-    pub async fn embed(&self, document: &Document) -> Result<Vec<PointStruct>> {
-        let tokens = document
-            .sections
-            .iter()
-            .map(|s| {
-                format!(
-                    "{}{}",
-                    s.title.as_ref().map_or(String::new(), |t| format!("{t}: ")),
-                    s.content.replace('\n', " ")
-                )
-            })
-            .collect::<Vec<String>>();
-
-        let mut responses = Vec::new();
+impl Default for OpenAI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        for token in tokens {
-            let client = self.client.clone();
-            let request = CreateEmbeddingRequestArgs::default()
-                .model("text-embedding-ada-002")
-                .input(token)
-                .build()?;
+#[async_trait]
+impl Embedder for OpenAI {
+    fn model(&self) -> &str {
+        EMBEDDING_MODEL
+    }
 
-            responses.push(tokio::spawn(async move {
-                client.embeddings().create(request).await
-            }));
-        }
+    fn dimensions(&self) -> usize {
+        EMBEDDING_SIZE
+    }
 
-        let responses = future::join_all(responses).await;
+    async fn embed_batch(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let inputs = inputs
+            .iter()
+            .map(|input| tokens::truncate(input, EMBEDDING_MAX_TOKENS))
+            .collect();
 
-        let mut points = Vec::new();
+        embed_in_batches(inputs, &EMBEDDING_BATCHES, |batch| async move {
+            let request = CreateEmbeddingRequestArgs::default()
+                .model(EMBEDDING_MODEL)
+                .input(batch)
+                .build()?;
 
-        for (i, response) in responses.into_iter().enumerate() {
-            let input = &document.sections[i];
-            let response = response??;
+            let mut response = self.client.embeddings().create(request).await?;
 
             info!(
-                text = input.content,
-                "Generated embeddings for {} tokens.", response.usage.total_tokens
+                "Generated {} embeddings for {} tokens.",
+                response.data.len(),
+                response.usage.total_tokens
             );
 
-            let point = PointStruct {
-                id: Uuid::new_v4().to_string(),
-                payload: Payload {
-                    text: input.content.clone(),
-                    path: document.path.clone(),
-                    page_title: document.title.clone(),
-                    title: input.title.clone().unwrap_or_default(),
-                },
-                vector: response
-                    .data
-                    .first()
-                    .ok_or_else(|| anyhow!("Could not find embedding"))?
-                    .embedding
-                    .clone(),
-            };
-
-            points.push(point);
-        }
+            response.data.sort_by_key(|embedding| embedding.index);
 
-        Ok(points)
-    }
-
-    /// Embeds a string into a vector of points.
-    ///
-    /// # Errors
-    ///
-    /// This function will panic if the Embeddings API returns an error.
-    pub async fn raw_embed(&self, text: &str) -> Result<Vec<f32>> {
-        let request = CreateEmbeddingRequestArgs::default()
-            .model("text-embedding-ada-002")
-            .input(text)
-            .build()?;
-
-        let response = self.client.embeddings().create(request).await?;
-
-        info!(
-            text,
-            "Generated embeddings for {} tokens.", response.usage.total_tokens
-        );
-
-        Ok(response
-            .data
-            .first()
-            .ok_or_else(|| anyhow!("Could not find embedding"))?
-            .embedding
-            .clone())
-    }
-
-    /// Prompts GPT-3 to generate an answer.
-    ///
-    /// # Errors
-    ///
-    /// This function will panic if the Completions API returns an error.
-    pub async fn chat(&self, messages: Vec<ChatCompletionRequestMessage>) -> Result<String> {
-        let request = CreateChatCompletionRequestArgs::default()
-            .model("gpt-3.5-turbo")
-            .temperature(0.5)
-            .messages(messages.clone())
-            .build()?;
-
-        let response = self.client.chat().create(request).await?;
-
-        info!(messages = ?messages, usage = ?response.usage, "Prompted gpt-3.5-turbo model.");
-
-        Ok(response
-            .choices
-            .first()
-            .ok_or_else(|| anyhow!("Could not find completion"))?
-            .message
-            .content
-            .trim()
-            .to_string())
+            Ok::<_, anyhow::Error>(
+                response
+                    .data
+                    .into_iter()
+                    .map(|embedding| embedding.embedding)
+                    .collect(),
+            )
+        })
+        .await
     }
+}
 
-    /// Prompts GPT-3 to generate an answer, returning a stream of responses.
-    ///
-    /// # Errors
-    ///
-    /// This function will panic if the Completions API returns an error.
-    pub async fn chat_stream(
+#[async_trait]
+impl ChatModel for OpenAI {
+    async fn chat_stream(
         &self,
-        messages: Vec<ChatCompletionRequestMessage>,
-    ) -> Result<ChatCompletionResponseStream> {
-        let request = CreateChatCompletionRequestArgs::default()
-            .model("gpt-3.5-turbo")
-            .temperature(0.5)
-            .messages(messages.clone())
-            .build()?;
+        messages: Vec<ChatMessage>,
+        settings: &ChatSettings,
+    ) -> Result<TextStream> {
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request
+            .model(&settings.model)
+            .temperature(settings.temperature)
+            .messages(
+                messages
+                    .iter()
+                    .map(Into::into)
+                    .collect::<Vec<ChatCompletionRequestMessage>>(),
+            );
+
+        if let Some(max_tokens) = settings.max_tokens {
+            request.max_tokens(max_tokens);
+        }
 
         info!(
             messages = ?messages,
-            "Prompting gpt-3.5-turbo model and streaming output."
+            "Prompting {} model and streaming output.", settings.model
         );
 
-        Ok(self.client.chat().create_stream(request).await?)
+        let stream = self.client.chat().create_stream(request.build()?).await?;
+
+        Ok(stream
+            .map(|response| -> Result<String> {
+                Ok(response?
+                    .choices
+                    .into_iter()
+                    .filter_map(|c| c.delta.content)
+                    .collect())
+            })
+            .boxed())
     }
 }
 
-impl Default for OpenAI {
-    fn default() -> Self {
-        Self::new()
+impl From<&ChatMessage> for ChatCompletionRequestMessage {
+    fn from(message: &ChatMessage) -> Self {
+        Self {
+            name: None,
+            content: message.content.clone(),
+            role: match message.role {
+                Role::User => OpenAIRole::User,
+                Role::System => OpenAIRole::System,
+                Role::Assistant => OpenAIRole::Assistant,
+            },
+        }
     }
 }
@@ -0,0 +1,281 @@
+use anyhow::{bail, Result};
+
+use crate::tokens;
+
+/// Controls how section contents are split into chunks before embedding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkConfig {
+    /// The number of tokens a chunk should aim for. Chunks are only split on line boundaries when reaching it.
+    pub target_tokens: usize,
+    /// The number of tokens repeated from the end of a chunk at the start of the next one.
+    pub overlap: usize,
+    /// The number of tokens no chunk will ever exceed, even if that means splitting a line.
+    pub max_tokens: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            target_tokens: 300,
+            overlap: 40,
+            max_tokens: 500,
+        }
+    }
+}
+
+impl ChunkConfig {
+    /// Creates a chunking configuration, making sure its limits are consistent.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the configuration is invalid, see [`ChunkConfig::validate`].
+    pub fn new(target_tokens: usize, overlap: usize, max_tokens: usize) -> Result<Self> {
+        let config = Self {
+            target_tokens,
+            overlap,
+            max_tokens,
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Checks that the overlap is smaller than the target size, which is in turn no larger than the hard limit.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `overlap < target_tokens <= max_tokens` doesn't hold.
+    pub fn validate(&self) -> Result<()> {
+        if self.overlap >= self.target_tokens {
+            bail!(
+                "The chunk overlap ({}) should be smaller than the target chunk size ({})",
+                self.overlap,
+                self.target_tokens
+            );
+        }
+
+        if self.target_tokens > self.max_tokens {
+            bail!(
+                "The target chunk size ({}) can't exceed the maximum chunk size ({})",
+                self.target_tokens,
+                self.max_tokens
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// A piece of text that won't be split further, along with its token count.
+struct Unit {
+    text: String,
+    tokens: usize,
+}
+
+impl Unit {
+    fn new(text: String) -> Self {
+        Self {
+            tokens: tokens::count(&text),
+            text,
+        }
+    }
+}
+
+/// Splits content into chunks of roughly `target_tokens`, overlapping by `overlap` tokens and never exceeding `max_tokens`.
+pub fn chunk(content: &str, config: &ChunkConfig) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current: Vec<Unit> = Vec::new();
+
+    for unit in split_units(content, config.max_tokens) {
+        let current_tokens = current.iter().map(|u| u.tokens).sum::<usize>();
+
+        if !current.is_empty() && current_tokens + unit.tokens > config.target_tokens {
+            chunks.push(join(&current, config.max_tokens));
+            current = overlap(current, config.overlap);
+
+            let overlap_tokens = current.iter().map(|u| u.tokens).sum::<usize>();
+            if overlap_tokens + unit.tokens > config.max_tokens {
+                current.clear();
+            }
+        }
+
+        current.push(unit);
+    }
+
+    if !current.is_empty() {
+        chunks.push(join(&current, config.max_tokens));
+    }
+
+    chunks.into_iter().filter(|c| !c.is_empty()).collect()
+}
+
+/// Splits content into lines, breaking up any line longer than `max_tokens` into words (or, as a last resort, characters).
+fn split_units(content: &str, max_tokens: usize) -> Vec<Unit> {
+    content
+        .lines()
+        .flat_map(|line| {
+            let unit = Unit::new(format!("{line}\n"));
+            if unit.tokens <= max_tokens {
+                return vec![unit];
+            }
+
+            let mut units = Vec::new();
+            let mut current = String::new();
+
+            for word in line.split_inclusive(' ') {
+                let candidate = format!("{current}{word}");
+                if tokens::count(&candidate) <= max_tokens {
+                    current = candidate;
+                    continue;
+                }
+
+                if !current.is_empty() {
+                    units.push(Unit::new(std::mem::take(&mut current)));
+                }
+
+                if tokens::count(word) <= max_tokens {
+                    current = word.to_string();
+                } else {
+                    units.extend(split_word(word, max_tokens));
+                }
+            }
+
+            units.push(Unit::new(format!("{current}\n")));
+            units
+        })
+        .collect()
+}
+
+/// Splits a single word that doesn't fit in `max_tokens` into pieces that do.
+fn split_word(word: &str, max_tokens: usize) -> Vec<Unit> {
+    let mut units = Vec::new();
+    let mut rest = word;
+
+    while !rest.is_empty() {
+        let piece = tokens::truncate(rest, max_tokens.max(1));
+        let piece = if piece.is_empty() {
+            rest.chars().next().map(String::from).unwrap_or_default()
+        } else {
+            piece
+        };
+
+        rest = &rest[piece.len()..];
+        units.push(Unit::new(piece));
+    }
+
+    units
+}
+
+/// Keeps the trailing units of a chunk that fit in the overlap budget.
+fn overlap(units: Vec<Unit>, overlap_tokens: usize) -> Vec<Unit> {
+    let mut budget = overlap_tokens;
+    let mut kept = Vec::new();
+
+    for unit in units.into_iter().rev() {
+        if unit.tokens > budget {
+            break;
+        }
+
+        budget -= unit.tokens;
+        kept.push(unit);
+    }
+
+    kept.reverse();
+    kept
+}
+
+fn join(units: &[Unit], max_tokens: usize) -> String {
+    let text = units.iter().map(|u| u.text.as_str()).collect::<String>();
+
+    // Tokens can merge across unit boundaries, so enforce the hard limit on the final text too.
+    tokens::truncate(text.trim(), max_tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: ChunkConfig = ChunkConfig {
+        target_tokens: 12,
+        overlap: 5,
+        max_tokens: 16,
+    };
+
+    #[test]
+    fn rejects_inconsistent_limits() {
+        // When
+        let overlapping = ChunkConfig::new(100, 100, 200).unwrap_err();
+        let oversized = ChunkConfig::new(300, 40, 200).unwrap_err();
+
+        // Then
+        assert_eq!(
+            overlapping.to_string(),
+            "The chunk overlap (100) should be smaller than the target chunk size (100)"
+        );
+        assert_eq!(
+            oversized.to_string(),
+            "The target chunk size (300) can't exceed the maximum chunk size (200)"
+        );
+        assert_eq!(
+            ChunkConfig::new(300, 40, 500).unwrap(),
+            ChunkConfig::default()
+        );
+    }
+
+    #[test]
+    fn keeps_short_content_in_one_chunk() {
+        // When
+        let chunks = chunk("The quick brown fox\njumped over the lazy dog", &CONFIG);
+
+        // Then
+        assert_eq!(
+            chunks,
+            vec!["The quick brown fox\njumped over the lazy dog"]
+        );
+    }
+
+    #[test]
+    fn splits_on_lines_with_overlap() {
+        // When
+        let chunks = chunk(
+            "The quick brown fox\nand ran away\njumped over the lazy dog\ninto the woods",
+            &CONFIG,
+        );
+
+        // Then
+        assert_eq!(
+            chunks,
+            vec![
+                "The quick brown fox\nand ran away",
+                "and ran away\njumped over the lazy dog",
+                "into the woods"
+            ]
+        );
+    }
+
+    #[test]
+    fn never_exceeds_the_hard_limit() {
+        // Given
+        let paragraph = "lorem ipsum dolor sit amet ".repeat(40);
+
+        // When
+        let chunks = chunk(&paragraph, &CONFIG);
+
+        // Then
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| tokens::count(c) <= CONFIG.max_tokens));
+    }
+
+    #[test]
+    fn splits_long_words() {
+        // Given
+        let word = "a".repeat(500);
+
+        // When
+        let chunks = chunk(&word, &CONFIG);
+
+        // Then
+        assert_eq!(chunks.concat(), word);
+        assert!(chunks.iter().all(|c| tokens::count(c) <= CONFIG.max_tokens));
+    }
+}
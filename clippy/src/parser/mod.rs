@@ -1,32 +1,64 @@
-mod heading;
+mod chunker;
+
+pub use chunker::ChunkConfig;
 
 use anyhow::{anyhow, Result};
-use heading::Heading;
 use inflector::Inflector;
 use lazy_static::lazy_static;
-use map_macro::map;
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
 use regex::Regex;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs::{self, DirEntry},
+    ops::Range,
     path::Path,
 };
 use yaml_front_matter::YamlFrontMatter;
 
 lazy_static! {
     static ref JSX_COMMENT_RE: Regex = Regex::new(r"\{/\*[\s\S]*?\*/}").unwrap();
+    static ref HTML_COMMENT_RE: Regex = Regex::new(r"<!--[\s\S]*?-->").unwrap();
+    static ref HTML_TAG_RE: Regex = Regex::new(r"</?[A-Za-z][^>]*>").unwrap();
     static ref EMPTY_BLOCK_RE: Regex = Regex::new(r"---\s*---").unwrap();
     static ref IMPORT_RE: Regex =
-        Regex::new(r#"import\s+(?:[\{}]?\s*[\w,\s{}]+\s+from\s+)?['"].+?['"];?"#).unwrap();
+        Regex::new(r#"^import\s+(?:[\w*\s{},]+\s+from\s+)?['"][^'"\n]+['"];?$"#).unwrap();
+    static ref EXPORT_RE: Regex =
+        Regex::new(r"^export\s+(?:const|let|var|function|async|class|default|\{|\*)").unwrap();
+    static ref EXPORT_END_RE: Regex = Regex::new(r#"(?:[;}]|\bfrom\s+['"][^'"\n]+['"])$"#).unwrap();
+    static ref PARSER_OPTIONS: Options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_STRIKETHROUGH;
 }
 
 #[derive(Debug, serde::Deserialize, Default)]
 pub struct FrontMatter {
     pub title: Option<String>,
     pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Any other front matter fields, like `version` or `language`.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 impl FrontMatter {
+    /// The scalar fields of the front matter that aren't used elsewhere, as strings.
+    pub(crate) fn metadata(&self) -> HashMap<String, String> {
+        self.extra
+            .iter()
+            .filter_map(|(key, value)| {
+                let value = match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    serde_json::Value::Number(_) | serde_json::Value::Bool(_) => value.to_string(),
+                    _ => return None,
+                };
+
+                Some((key.clone(), value))
+            })
+            .collect()
+    }
+
     pub(crate) fn ensure_title(&self, path: &Path) -> Result<String> {
         Ok(if let Some(title) = &self.title {
             title.clone()
@@ -46,7 +78,7 @@ pub fn parse_meta(content: &str) -> Result<(FrontMatter, String), Box<dyn std::e
     Ok((document.metadata, document.content.trim().to_owned()))
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct MarkdownSection {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
@@ -79,41 +111,28 @@ impl MarkdownSection {
 
 struct State {
     current_section: usize,
-    pub is_inside_code_block: bool,
     sections: Vec<MarkdownSection>,
-    depth_map: HashMap<usize, String>,
+    depth_map: BTreeMap<usize, String>,
 }
 
 impl State {
     pub fn with_title(title: Option<String>) -> Self {
         Self {
             current_section: 0,
-            is_inside_code_block: false,
             sections: vec![MarkdownSection::default()],
-            depth_map: title.map_or_else(HashMap::new, |title| {
-                map! {
-                    1 => title
-                }
-            }),
+            depth_map: title.map_or_else(BTreeMap::new, |title| BTreeMap::from([(1, title)])),
         }
     }
 
-    pub fn toggle_code_block(&mut self) {
-        self.is_inside_code_block = !self.is_inside_code_block;
-    }
+    pub fn compute_title(&mut self, depth: usize, content: &str) -> String {
+        self.depth_map.split_off(&depth);
+        self.depth_map.insert(depth, content.to_string());
 
-    pub fn compute_title(&mut self, heading: &Heading) -> String {
         self.depth_map
-            .insert(heading.depth, heading.content.clone());
-
-        let mut title = heading.content.clone();
-        for (depth, sec_title) in &self.depth_map {
-            if depth < &heading.depth {
-                title = format!("{sec_title}: {title}");
-            }
-        }
-
-        title
+            .values()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(": ")
     }
 
     pub fn push_section(&mut self, section: MarkdownSection) {
@@ -122,75 +141,218 @@ impl State {
     }
 
     pub fn push_line(&mut self, line: &str) {
-        let curr_content = &self.sections[self.current_section].content;
-
-        if !self.is_inside_code_block && curr_content.ends_with('\n') && curr_content.len() > 200 {
-            self.push_section(MarkdownSection::with_title(
-                self.sections[self.current_section].title.clone(),
-            ));
-
-            return self.push_line(line);
-        }
-
         self.sections[self.current_section].append(line);
     }
 
-    pub fn get_sections(self) -> Vec<MarkdownSection> {
+    pub fn get_sections(self, config: &ChunkConfig) -> Vec<MarkdownSection> {
         self.sections
             .into_iter()
-            .map(|section| {
-                let content = EMPTY_BLOCK_RE
-                    .replace_all(section.content.trim(), "")
-                    .trim()
-                    .to_string();
-
-                MarkdownSection { content, ..section }
+            .flat_map(|section| {
+                let chunks = chunker::chunk(
+                    EMPTY_BLOCK_RE
+                        .replace_all(section.content.trim(), "")
+                        .trim(),
+                    config,
+                );
+
+                chunks.into_iter().map(move |content| MarkdownSection {
+                    content,
+                    title: section.title.clone(),
+                })
             })
-            .filter(|section| !section.content.is_empty())
             .collect::<Vec<_>>()
     }
 }
 
-pub fn extract_sections(content: &str, metadata: &mut FrontMatter) -> Vec<MarkdownSection> {
+/// Accumulates the text of the block currently being walked, until it's complete and can be pushed to a section.
+#[derive(Default)]
+struct Block {
+    text: String,
+    is_inline: bool,
+    heading: Option<usize>,
+    /// How many list items and block quotes the block is nested in. MDX only allows `import` and `export` statements at the top level.
+    nesting: usize,
+}
+
+impl Block {
+    fn push(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    fn take(&mut self) -> String {
+        std::mem::take(&mut self.text).trim().to_string()
+    }
+}
+
+/// Splits a markdown (or MDX) document into sections, delimited by its headings.
+pub fn extract_sections(
+    content: &str,
+    metadata: &mut FrontMatter,
+    config: &ChunkConfig,
+) -> Vec<MarkdownSection> {
+    let content = strip_comments(content);
     let mut state = State::with_title(metadata.title.clone());
+    let mut block = Block::default();
 
-    for mut line in content.lines().map(ToString::to_string) {
-        if line.starts_with("```") {
-            state.toggle_code_block();
+    let mut flush = |state: &mut State, block: &mut Block| {
+        let text = block.take();
+        if text.is_empty() || (block.nesting == 0 && is_esm(&text)) {
+            return;
         }
 
-        if !state.is_inside_code_block {
-            if IMPORT_RE.is_match(&line) {
-                continue;
-            }
+        if let Some(title) = state.depth_map.get(&1) {
+            metadata.title = Some(title.clone());
+        }
 
-            line = JSX_COMMENT_RE.replace_all(&line, "").to_string();
+        state.push_line(&text);
+    };
 
-            let heading = Heading::try_parse(&line);
+    for event in Parser::new_ext(&content, *PARSER_OPTIONS) {
+        match event {
+            Event::Start(Tag::Heading(level, ..)) => {
+                flush(&mut state, &mut block);
+                block.heading = Some(level as usize);
+                block.is_inline = true;
+            }
+            Event::End(Tag::Heading(..)) => {
+                let depth = block.heading.take().unwrap_or(1);
+                let content = block.take();
+                block.is_inline = false;
 
-            if let Some(heading) = heading {
-                let title = state.compute_title(&heading);
+                let title = state.compute_title(depth, &content);
                 state.push_section(MarkdownSection::with_title(Some(title)));
+            }
+            Event::Start(Tag::Paragraph | Tag::TableCell) => block.is_inline = true,
+            Event::End(Tag::TableCell) => {
+                block.is_inline = false;
+                block.push(" | ");
+            }
+            Event::Start(Tag::Item) => {
+                flush(&mut state, &mut block);
+                block.nesting += 1;
+                block.push("- ");
+            }
+            Event::Start(Tag::BlockQuote) => block.nesting += 1,
+            Event::Start(Tag::CodeBlock(kind)) => {
+                flush(&mut state, &mut block);
+                let lang = match kind {
+                    CodeBlockKind::Fenced(lang) => lang.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+
+                block.push(&format!("```{lang}\n"));
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                block.push("```");
+                flush(&mut state, &mut block);
+            }
+            Event::End(
+                tag @ (Tag::Paragraph
+                | Tag::Item
+                | Tag::TableHead
+                | Tag::TableRow
+                | Tag::BlockQuote),
+            ) => {
+                block.is_inline = false;
+                block.text = block.text.trim_end_matches(" | ").to_string();
+                flush(&mut state, &mut block);
+
+                if matches!(tag, Tag::Item | Tag::BlockQuote) {
+                    block.nesting = block.nesting.saturating_sub(1);
+                }
+            }
+            Event::Text(text) => block.push(&text),
+            Event::Code(code) => block.push(&format!("`{code}`")),
+            Event::Html(html) => {
+                block.push(&HTML_TAG_RE.replace_all(&html, ""));
 
-                continue;
+                if !block.is_inline {
+                    flush(&mut state, &mut block);
+                }
             }
+            Event::SoftBreak | Event::HardBreak => block.push("\n"),
+            Event::TaskListMarker(checked) => block.push(if checked { "[x] " } else { "[ ] " }),
+            _ => {}
         }
+    }
 
-        if let Some(title) = state.depth_map.get(&1) {
-            metadata.title = Some(title.clone());
+    flush(&mut state, &mut block);
+
+    state.get_sections(config)
+}
+
+/// Removes JSX and HTML comments, leaving code blocks and inline code untouched.
+fn strip_comments(content: &str) -> String {
+    let code_ranges = Parser::new_ext(content, *PARSER_OPTIONS)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) => Some(range),
+            _ => None,
+        })
+        .collect::<Vec<Range<usize>>>();
+
+    let is_code = |range: &Range<usize>| {
+        code_ranges
+            .iter()
+            .any(|code| code.start < range.end && range.start < code.end)
+    };
+
+    let mut comments = JSX_COMMENT_RE
+        .find_iter(content)
+        .chain(HTML_COMMENT_RE.find_iter(content))
+        .map(|m| m.range())
+        .filter(|range| !is_code(range))
+        .collect::<Vec<_>>();
+    comments.sort_by_key(|range| range.start);
+
+    let mut stripped = String::with_capacity(content.len());
+    let mut cursor = 0;
+    for range in comments {
+        if range.start < cursor {
+            continue;
         }
 
-        state.push_line(&line);
+        stripped.push_str(&content[cursor..range.start]);
+        cursor = range.end;
+    }
+    stripped.push_str(&content[cursor..]);
+
+    stripped
+}
+
+/// Whether a block only holds MDX `import` or `export` statements, each starting a line and shaped like one, so prose starting with those words is kept.
+fn is_esm(text: &str) -> bool {
+    let mut statements: Vec<String> = Vec::new();
+
+    for line in text.lines() {
+        let starts_statement = matches!(line.split_whitespace().next(), Some("import" | "export"));
+
+        match statements.last_mut() {
+            Some(statement) if !starts_statement => {
+                statement.push('\n');
+                statement.push_str(line);
+            }
+            _ => statements.push(line.to_string()),
+        }
     }
 
-    state.get_sections()
+    statements.iter().all(|statement| {
+        let statement = statement.trim_end();
+
+        IMPORT_RE.is_match(statement)
+            || (EXPORT_RE.is_match(statement) && EXPORT_END_RE.is_match(statement))
+    })
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Document {
     pub path: String,
     pub title: String,
-    description: Option<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
     pub sections: Vec<MarkdownSection>,
 }
 
@@ -201,7 +363,7 @@ pub struct Document {
 /// - If the file cannot be parsed.
 /// - If the file path cannot be converted to a string.
 /// - If the file path cannot be stripped from the base path.
-pub fn into_document(file: &DirEntry, base_path: String) -> Result<Document> {
+pub fn into_document(file: &DirEntry, base_path: String, config: &ChunkConfig) -> Result<Document> {
     let content = fs::read_to_string(file.path())?;
 
     let (mut metadata, content) = if content.trim().starts_with("---") {
@@ -216,11 +378,13 @@ pub fn into_document(file: &DirEntry, base_path: String) -> Result<Document> {
         (FrontMatter::default(), content)
     };
 
-    let sections = extract_sections(&content, &mut metadata);
+    let sections = extract_sections(&content, &mut metadata, config);
 
     Ok(Document {
         sections,
         title: metadata.ensure_title(&file.path())?,
+        metadata: metadata.metadata(),
+        tags: metadata.tags,
         description: metadata.description,
         path: format!(
             "/{}",
@@ -232,3 +396,145 @@ pub fn into_document(file: &DirEntry, base_path: String) -> Result<Document> {
         ),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sections(content: &str) -> Vec<(Option<String>, String)> {
+        extract_sections(
+            content,
+            &mut FrontMatter::default(),
+            &ChunkConfig::default(),
+        )
+        .into_iter()
+        .map(|s| (s.title, s.content))
+        .collect()
+    }
+
+    #[test]
+    fn splits_sections_on_headings() {
+        // When
+        let sections =
+            sections("# Guide\nIntro\n## Install\nRun it\n### Linux\nUse apt\n## Usage\nCall it");
+
+        // Then
+        assert_eq!(
+            sections,
+            vec![
+                (Some("Guide".to_string()), "Intro".to_string()),
+                (Some("Guide: Install".to_string()), "Run it".to_string()),
+                (
+                    Some("Guide: Install: Linux".to_string()),
+                    "Use apt".to_string()
+                ),
+                (Some("Guide: Usage".to_string()), "Call it".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_setext_headings() {
+        // When
+        let sections = sections("Title\n=====\n\nSome text");
+
+        // Then
+        assert_eq!(
+            sections,
+            vec![(Some("Title".to_string()), "Some text".to_string())]
+        );
+    }
+
+    #[test]
+    fn does_not_treat_hashtags_as_headings() {
+        // When
+        let sections = sections("#hashtag in a paragraph");
+
+        // Then
+        assert_eq!(
+            sections,
+            vec![(None, "#hashtag in a paragraph".to_string())]
+        );
+    }
+
+    #[test]
+    fn keeps_headings_inside_code_blocks() {
+        // When
+        let sections = sections("~~~bash\n# not a heading\n~~~\n\n    # indented code\n");
+
+        // Then
+        assert_eq!(
+            sections,
+            vec![(
+                None,
+                "```bash\n# not a heading\n```\n```\n# indented code\n```".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn strips_mdx_syntax() {
+        // When
+        let sections = sections(indoc::indoc! {r#"
+            import { Callout } from "nextra-theme-docs";
+            export const meta = {
+              title: "Hello",
+            };
+
+            # Hello {/* a comment */}
+
+            <Callout type="info">
+
+            Nested **markdown** content.
+
+            </Callout>
+
+            <!-- an html comment -->
+            Export your data with `export {a}`.
+        "#});
+
+        // Then
+        assert_eq!(
+            sections,
+            vec![(
+                Some("Hello".to_string()),
+                "Nested markdown content.\nExport your data with `export {a}`.".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn keeps_prose_starting_with_esm_keywords() {
+        // When
+        let sections = sections(indoc::indoc! {r#"
+            Export your settings from the dashboard.
+
+            export default settings apply to every project.
+
+            import statements from "react" are kept too.
+
+            > export const quoted = 1;
+        "#});
+
+        // Then
+        assert_eq!(
+            sections,
+            vec![(
+                None,
+                "Export your settings from the dashboard.\nexport default settings apply to every project.\nimport statements from \"react\" are kept too.\nexport const quoted = 1;".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn renders_lists_and_tables() {
+        // When
+        let sections = sections("- one\n- [x] two\n\n| a | b |\n|---|---|\n| 1 | 2 |");
+
+        // Then
+        assert_eq!(
+            sections,
+            vec![(None, "- one\n- [x] two\na | b\n1 | 2".to_string())]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{bail, Result};
+use indoc::indoc;
+use std::str::FromStr;
+
+/// The template used by projects that don't have their own.
+pub const DEFAULT_TEMPLATE: &str = indoc! {"
+    You are a very enthusiastic {{ project }} representative who loves to help people! Given the following numbered sections from the documentation, give a comprehensive answer to the user's question.
+    If you are unsure or the question doesn't relate to {{ project }}, say \"Sorry, I am not sure how to answer that.\"
+
+    Documentation:
+    ---
+    {{ sources }}"
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Sources,
+    Question,
+    Project,
+}
+
+impl FromStr for Placeholder {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "sources" => Ok(Self::Sources),
+            "question" => Ok(Self::Question),
+            "project" => Ok(Self::Project),
+            _ => bail!(
+                "Unknown placeholder `{{{{ {name} }}}}`, expected one of `{{{{ sources }}}}`, `{{{{ question }}}}` or `{{{{ project }}}}`."
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// The values a prompt template is rendered with.
+#[derive(Debug, Clone, Copy)]
+pub struct PromptValues<'a> {
+    /// The numbered documentation sections retrieved for the question.
+    pub sources: &'a str,
+    pub question: &'a str,
+    /// The name of the project the documentation belongs to.
+    pub project: &'a str,
+}
+
+/// A system prompt with `{{ sources }}`, `{{ question }}` and `{{ project }}` placeholders, which lets projects pick their assistant's persona and wording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    segments: Vec<Segment>,
+}
+
+impl PromptTemplate {
+    /// Parses a template, making sure it only uses known placeholders and includes the sources.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a placeholder is unknown or left unclosed, or if the template never includes `{{ sources }}`.
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+
+            let Some(end) = rest[start..].find("}}") else {
+                bail!(
+                    "Unclosed placeholder, expected `}}}}` after `{}`.",
+                    &rest[start..]
+                );
+            };
+
+            let name = rest[start + 2..start + end].trim();
+            segments.push(Segment::Placeholder(name.parse()?));
+            rest = &rest[start + end + 2..];
+        }
+
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+
+        if !segments.contains(&Segment::Placeholder(Placeholder::Sources)) {
+            bail!("The template must include the documentation with `{{{{ sources }}}}`.");
+        }
+
+        Ok(Self { segments })
+    }
+
+    #[must_use]
+    pub fn render(&self, values: &PromptValues) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.as_str(),
+                Segment::Placeholder(Placeholder::Sources) => values.sources,
+                Segment::Placeholder(Placeholder::Question) => values.question,
+                Segment::Placeholder(Placeholder::Project) => values.project,
+            })
+            .collect()
+    }
+}
+
+impl Default for PromptTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_TEMPLATE).expect("The default template should be valid.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_placeholders() {
+        // Given
+        let template = PromptTemplate::parse(
+            "You answer questions about {{project}}.\n{{ sources }}\nQ: {{ question }}",
+        )
+        .unwrap();
+
+        // When
+        let prompt = template.render(&PromptValues {
+            sources: "[1] Install with cargo.",
+            question: "How do I install it?",
+            project: "Clippy",
+        });
+
+        // Then
+        assert_eq!(
+            prompt,
+            "You answer questions about Clippy.\n[1] Install with cargo.\nQ: How do I install it?"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!(PromptTemplate::parse("No documentation here.").is_err());
+        assert!(PromptTemplate::parse("{{ sources }} {{ answer }}").is_err());
+        assert!(PromptTemplate::parse("{{ sources }} {{ question").is_err());
+        assert!(PromptTemplate::parse(DEFAULT_TEMPLATE).is_ok());
+    }
+}
@@ -1,40 +1,255 @@
-use std::env;
+use std::{env, str::FromStr, time::Duration};
 
-use anyhow::Result;
-use futures::future::join_all;
-use reqwest::Client;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use futures::{stream, StreamExt};
+use reqwest::{Client, StatusCode};
 use serde_json::Value;
-use tracing::debug;
+use tracing::{debug, warn};
 
-const EMBEDDING_SIZE: i32 = 1536;
+use crate::store::{
+    path_prefixes, CollectionInfo, Filter, Payload, PointResult, PointStruct, VectorStore,
+};
 
+const SCROLL_PAGE_SIZE: usize = 256;
+
+/// Controls how points are sent to Qdrant when upserting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpsertOptions {
+    /// The number of points sent in a single request.
+    pub batch_size: usize,
+    /// The number of requests running at the same time.
+    pub concurrency: usize,
+    /// How many times a request that failed for a transient reason is retried.
+    pub retries: u32,
+    /// How long to wait before the first retry. The delay doubles after every attempt.
+    pub retry_delay: Duration,
+    /// Whether Qdrant should only respond once the points are indexed, so they can be searched right away.
+    pub wait: bool,
+}
+
+impl Default for UpsertOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 64,
+            concurrency: 4,
+            retries: 3,
+            retry_delay: Duration::from_millis(500),
+            wait: true,
+        }
+    }
+}
+
+impl UpsertOptions {
+    /// Reads the options from `$QDRANT_BATCH_SIZE`, `$QDRANT_CONCURRENCY` and `$QDRANT_WAIT`, using the defaults for the ones that aren't set.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if one of the variables is set to an invalid value.
+    pub fn from_env() -> Result<Self> {
+        fn var<T: FromStr>(name: &str, default: T) -> Result<T>
+        where
+            T::Err: std::error::Error + Send + Sync + 'static,
+        {
+            env::var(name).map_or(Ok(default), |value| {
+                value.parse().with_context(|| format!("Invalid ${name}"))
+            })
+        }
+
+        let defaults = Self::default();
+
+        Ok(Self {
+            batch_size: var("QDRANT_BATCH_SIZE", defaults.batch_size)?.max(1),
+            concurrency: var("QDRANT_CONCURRENCY", defaults.concurrency)?.max(1),
+            wait: var("QDRANT_WAIT", defaults.wait)?,
+            ..defaults
+        })
+    }
+}
+
+/// Whether a failed request is worth retrying, as opposed to one Qdrant rejected. Requests that got no response at all are retried too.
+fn is_transient(status: Option<StatusCode>) -> bool {
+    status.map_or(true, |status| {
+        status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+    })
+}
+
+/// A point as stored in Qdrant, with the prefixes of its path indexed so they can be filtered on.
+#[derive(serde::Serialize)]
+struct IndexedPoint<'a> {
+    id: &'a str,
+    vector: &'a [f32],
+    payload: IndexedPayload<'a>,
+}
+
+#[derive(serde::Serialize)]
+struct IndexedPayload<'a> {
+    #[serde(flatten)]
+    payload: &'a Payload,
+    path_prefixes: Vec<String>,
+}
+
+impl<'a> From<&'a PointStruct> for IndexedPoint<'a> {
+    fn from(point: &'a PointStruct) -> Self {
+        Self {
+            id: &point.id,
+            vector: &point.vector,
+            payload: IndexedPayload {
+                payload: &point.payload,
+                path_prefixes: path_prefixes(&point.payload.path),
+            },
+        }
+    }
+}
+
+/// Translates a filter into Qdrant's filter syntax, requiring every condition to match.
+fn qdrant_filter(filter: &Filter) -> Value {
+    let mut conditions = Vec::new();
+
+    if let Some(prefix) = &filter.path_prefix {
+        conditions.push(("path_prefixes".to_string(), prefix));
+    }
+
+    if let Some(page_title) = &filter.page_title {
+        conditions.push(("page_title".to_string(), page_title));
+    }
+
+    for (key, value) in &filter.metadata {
+        conditions.push((format!("metadata.{key}"), value));
+    }
+
+    for tag in &filter.tags {
+        conditions.push(("tags".to_string(), tag));
+    }
+
+    serde_json::json!({
+        "must": conditions
+            .into_iter()
+            .map(|(key, value)| serde_json::json!({ "key": key, "match": { "value": value } }))
+            .collect::<Vec<_>>()
+    })
+}
+
+#[derive(serde::Deserialize)]
+struct QdrantResponse<T> {
+    result: T,
+}
+
+#[derive(serde::Deserialize)]
+struct CollectionList {
+    collections: Vec<CollectionName>,
+}
+
+#[derive(serde::Deserialize)]
+struct CollectionName {
+    name: String,
+}
+
+#[derive(serde::Deserialize)]
+struct AliasList {
+    aliases: Vec<AliasDescription>,
+}
+
+#[derive(serde::Deserialize)]
+struct AliasDescription {
+    alias_name: String,
+    collection_name: String,
+}
+
+#[derive(serde::Deserialize)]
+struct CollectionDescription {
+    points_count: Option<usize>,
+    config: CollectionConfig,
+}
+
+#[derive(serde::Deserialize)]
+struct CollectionConfig {
+    params: CollectionParams,
+}
+
+#[derive(serde::Deserialize)]
+struct CollectionParams {
+    vectors: VectorParams,
+}
+
+#[derive(serde::Deserialize)]
+struct VectorParams {
+    size: usize,
+}
+
+#[derive(serde::Deserialize)]
+struct ScrolledPoint {
+    id: String,
+    payload: Payload,
+}
+
+#[derive(Debug, Clone)]
 pub struct Qdrant {
     client: Client,
     base_url: String,
+    upsert: UpsertOptions,
 }
 
 impl Qdrant {
     #[must_use]
-    pub fn new() -> Self {
+    pub fn new<S: Into<String>>(base_url: S) -> Self {
         Self {
             client: Client::new(),
-            base_url: env::var("QDRANT_URL").expect("$QDRANT_URL not set"),
+            base_url: base_url.into(),
+            upsert: UpsertOptions::default(),
         }
     }
 
-    /// Creates a new Qdrant collection.
+    /// Creates a Qdrant client pointing to `$QDRANT_URL`, with upsert options read from the environment.
     ///
     /// # Errors
     ///
-    /// This function will panic if the Qdrant API returns an error.
-    pub async fn create_collection(&self, name: &str) -> Result<()> {
+    /// This function will return an error if `$QDRANT_URL` is not set, or the upsert options are invalid.
+    pub fn from_env() -> Result<Self> {
+        Ok(
+            Self::new(env::var("QDRANT_URL").context("$QDRANT_URL not set")?)
+                .with_upsert_options(UpsertOptions::from_env()?),
+        )
+    }
+
+    #[must_use]
+    pub const fn with_upsert_options(mut self, options: UpsertOptions) -> Self {
+        self.upsert = options;
+        self
+    }
+
+    /// Applies a list of alias actions, which Qdrant performs atomically.
+    async fn update_aliases(&self, actions: Value) -> Result<()> {
+        self.client
+            .post(&format!("{}/collections/aliases", self.base_url))
+            .json(&serde_json::json!({ "actions": actions }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn collection(&self, name: &str) -> Collection {
+        Collection::new(
+            self.client.clone(),
+            format!("{}/collections/{name}", self.base_url),
+        )
+        .with_upsert_options(self.upsert)
+    }
+}
+
+#[async_trait]
+impl VectorStore for Qdrant {
+    async fn create_collection(&self, name: &str, dimensions: usize) -> Result<()> {
         self.client
             .put(&format!("{}/collections/{name}", self.base_url))
             .json(&serde_json::json!({
                 "name": name,
                 "vectors": {
                     "distance": "Cosine",
-                    "size": EMBEDDING_SIZE,
+                    "size": dimensions,
                 }
             }))
             .send()
@@ -44,93 +259,368 @@ impl Qdrant {
         Ok(())
     }
 
-    #[must_use]
-    pub fn collection(self, name: &str) -> Collection {
-        Collection::new(self.client, format!("{}/collections/{name}", self.base_url))
+    async fn delete_collection(&self, name: &str) -> Result<()> {
+        let response = self
+            .client
+            .delete(&format!("{}/collections/{name}", self.base_url))
+            .send()
+            .await?;
+
+        if response.status() != StatusCode::NOT_FOUND {
+            response.error_for_status()?;
+        }
+
+        Ok(())
     }
-}
 
-impl Default for Qdrant {
-    fn default() -> Self {
-        Self::new()
+    async fn collection_exists(&self, name: &str) -> Result<bool> {
+        let response: QdrantResponse<CollectionList> = self
+            .client
+            .get(&format!("{}/collections", self.base_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response
+            .result
+            .collections
+            .iter()
+            .any(|collection| collection.name == name))
     }
-}
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, opg::OpgModel)]
-pub struct Payload {
-    pub text: String,
-    pub path: String,
-    pub title: String,
-    pub page_title: String,
-}
+    async fn collection_info(&self, name: &str) -> Result<CollectionInfo> {
+        let name = self.alias(name).await?.unwrap_or_else(|| name.to_string());
+        let response: QdrantResponse<CollectionDescription> = self
+            .client
+            .get(&format!("{}/collections/{name}", self.base_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
 
-#[derive(Debug, serde::Serialize)]
-pub struct PointStruct {
-    pub id: String,
-    pub vector: Vec<f32>,
-    pub payload: Payload,
-}
+        Ok(CollectionInfo {
+            points: response.result.points_count.unwrap_or_default(),
+            dimensions: response.result.config.params.vectors.size,
+        })
+    }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
-pub struct PointResult {
-    id: String,
-    score: f32,
-    pub payload: Payload,
+    async fn alias(&self, alias: &str) -> Result<Option<String>> {
+        let response: QdrantResponse<AliasList> = self
+            .client
+            .get(&format!("{}/aliases", self.base_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response
+            .result
+            .aliases
+            .into_iter()
+            .find(|description| description.alias_name == alias)
+            .map(|description| description.collection_name))
+    }
+
+    async fn set_alias(&self, alias: &str, collection: &str) -> Result<()> {
+        let mut actions = Vec::new();
+
+        // Deleting and recreating the alias in the same request swaps it without a moment where it doesn't exist.
+        if self.alias(alias).await?.is_some() {
+            actions.push(serde_json::json!({ "delete_alias": { "alias_name": alias } }));
+        }
+
+        actions.push(serde_json::json!({
+            "create_alias": { "alias_name": alias, "collection_name": collection }
+        }));
+
+        self.update_aliases(Value::Array(actions)).await
+    }
+
+    async fn delete_alias(&self, alias: &str) -> Result<()> {
+        self.update_aliases(serde_json::json!([{ "delete_alias": { "alias_name": alias } }]))
+            .await
+    }
+
+    async fn upsert(&self, collection: &str, points: &[PointStruct]) -> Result<()> {
+        self.collection(collection).upsert(points).await
+    }
+
+    async fn query(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        count: usize,
+        filter: &Filter,
+    ) -> Result<Vec<PointResult>> {
+        self.collection(collection)
+            .query(vector, count, filter)
+            .await
+    }
+
+    async fn ids(&self, collection: &str) -> Result<Vec<String>> {
+        self.collection(collection).ids().await
+    }
+
+    async fn payloads(&self, collection: &str) -> Result<Vec<(String, Payload)>> {
+        self.collection(collection).payloads().await
+    }
+
+    async fn delete(&self, collection: &str, ids: &[String]) -> Result<()> {
+        self.collection(collection).delete(ids).await
+    }
 }
 
 pub struct Collection {
     client: Client,
     base_url: String,
+    upsert: UpsertOptions,
 }
 
 impl Collection {
-    pub const fn new(client: Client, url: String) -> Self {
+    pub fn new(client: Client, url: String) -> Self {
         Self {
             client,
             base_url: url,
+            upsert: UpsertOptions::default(),
         }
     }
 
+    #[must_use]
+    pub const fn with_upsert_options(mut self, options: UpsertOptions) -> Self {
+        self.upsert = options;
+        self
+    }
+
+    /// Upserts the points in batches, retrying the ones that fail for transient reasons.
+    ///
+    /// Every batch is attempted even if others fail, so a single error doesn't leave the rest of the points behind.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error listing every batch that still failed after being retried.
     pub async fn upsert(&self, vectors: &[PointStruct]) -> Result<()> {
-        join_all(vectors.chunks(30).map(|chunk| async move {
-            self.client
-                .put(&format!("{}/points", self.base_url))
-                .json(&serde_json::json!({ "points": chunk }))
-                .send()
-                .await
-                .unwrap()
-                .error_for_status()
-                .unwrap();
-        }))
-        .await;
+        let batches = vectors
+            .chunks(self.upsert.batch_size.max(1))
+            .map(|batch| self.upsert_batch(batch))
+            .collect::<Vec<_>>();
+        let count = batches.len();
+
+        let errors = stream::iter(batches)
+            .buffer_unordered(self.upsert.concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .filter_map(Result::err)
+            .collect::<Vec<_>>();
+
+        if !errors.is_empty() {
+            bail!(
+                "Failed to upsert {} of {} batches: {}",
+                errors.len(),
+                count,
+                errors
+                    .iter()
+                    .map(|e| format!("{e:#}"))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+        }
 
         debug!("Upserted {} vectors", vectors.len());
 
         Ok(())
     }
 
-    pub async fn query(&self, vectors: Vec<f32>, count: usize) -> Result<Vec<PointResult>> {
+    async fn upsert_batch(&self, batch: &[PointStruct]) -> Result<()> {
+        let body = serde_json::json!({
+            "points": batch.iter().map(IndexedPoint::from).collect::<Vec<_>>()
+        });
+        let mut delay = self.upsert.retry_delay;
+        let mut attempt = 0;
+
+        loop {
+            let result = self
+                .client
+                .put(&format!("{}/points", self.base_url))
+                .query(&[("wait", self.upsert.wait)])
+                .json(&body)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < self.upsert.retries && is_transient(e.status()) => {
+                    warn!("Failed to upsert {} vectors, retrying: {e}", batch.len());
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to upsert {} vectors", batch.len()))
+                }
+            }
+        }
+    }
+
+    pub async fn query(
+        &self,
+        vectors: Vec<f32>,
+        count: usize,
+        filter: &Filter,
+    ) -> Result<Vec<PointResult>> {
+        let mut body = serde_json::json!({
+            "limit": count,
+            "vector": vectors,
+            "with_payload": true,
+        });
+
+        if !filter.is_empty() {
+            body["filter"] = qdrant_filter(filter);
+        }
+
         let results: Value = self
             .client
             .post(&format!("{}/points/search", self.base_url))
-            .json(&serde_json::json!({
-                "limit": count,
-                "vector": vectors,
-                "with_payload": true,
-            }))
+            .json(&body)
             .send()
             .await?
             .error_for_status()?
             .json()
             .await?;
 
-        Ok(results
+        results
             .get("result")
             .ok_or_else(|| anyhow::anyhow!("No result field in response"))?
             .as_array()
-            .unwrap()
+            .context("The result field of the response isn't an array")?
+            .iter()
+            .map(|r| {
+                serde_json::from_value::<PointResult>(r.clone()).context("Invalid search result")
+            })
+            .collect()
+    }
+
+    pub async fn ids(&self) -> Result<Vec<String>> {
+        Ok(self
+            .scroll(false)
+            .await?
             .iter()
-            .map(|r| serde_json::from_value::<PointResult>(r.clone()).unwrap())
+            .filter_map(|point| point.get("id"))
+            .map(|id| {
+                id.as_str()
+                    .map_or_else(|| id.to_string(), ToString::to_string)
+            })
             .collect())
     }
+
+    pub async fn payloads(&self) -> Result<Vec<(String, Payload)>> {
+        self.scroll(true)
+            .await?
+            .into_iter()
+            .map(|point| {
+                let point = serde_json::from_value::<ScrolledPoint>(point)?;
+                Ok((point.id, point.payload))
+            })
+            .collect()
+    }
+
+    /// Pages through every point in the collection, without their vectors.
+    async fn scroll(&self, with_payload: bool) -> Result<Vec<Value>> {
+        let mut points = Vec::new();
+        let mut offset = Value::Null;
+
+        loop {
+            let results: Value = self
+                .client
+                .post(&format!("{}/points/scroll", self.base_url))
+                .json(&serde_json::json!({
+                    "limit": SCROLL_PAGE_SIZE,
+                    "offset": offset,
+                    "with_payload": with_payload,
+                    "with_vector": false,
+                }))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            let result = results
+                .get("result")
+                .ok_or_else(|| anyhow::anyhow!("No result field in response"))?;
+
+            points.extend(
+                result
+                    .get("points")
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| anyhow::anyhow!("No points field in response"))?
+                    .iter()
+                    .cloned(),
+            );
+
+            offset = result
+                .get("next_page_offset")
+                .cloned()
+                .unwrap_or(Value::Null);
+            if offset.is_null() {
+                break;
+            }
+        }
+
+        Ok(points)
+    }
+
+    pub async fn delete(&self, ids: &[String]) -> Result<()> {
+        self.client
+            .post(&format!("{}/points/delete", self.base_url))
+            .json(&serde_json::json!({ "points": ids }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        debug!("Deleted {} vectors", ids.len());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn translates_filters() {
+        // Given
+        let filter = Filter {
+            path_prefix: Some("/api/".to_string()),
+            metadata: HashMap::from([("version".to_string(), "v2".to_string())]),
+            ..Filter::default()
+        };
+
+        // Then
+        assert_eq!(
+            qdrant_filter(&filter),
+            serde_json::json!({
+                "must": [
+                    { "key": "path_prefixes", "match": { "value": "/api/" } },
+                    { "key": "metadata.version", "match": { "value": "v2" } },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn retries_transient_failures_only() {
+        assert!(is_transient(None));
+        assert!(is_transient(Some(StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(is_transient(Some(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!is_transient(Some(StatusCode::BAD_REQUEST)));
+    }
 }
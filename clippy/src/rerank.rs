@@ -0,0 +1,300 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use indoc::formatdoc;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{collections::HashSet, fmt::Debug, sync::Arc};
+
+use crate::{
+    chat::{ChatMessage, ChatModel, ChatSettings, Role},
+    keyword::tokenize,
+    store::PointResult,
+    tokens,
+};
+
+lazy_static! {
+    static ref SCORE_RE: Regex =
+        Regex::new(r"(?m)^\s*\[?(\d+)\]?\s*[:=\-]\s*(\d+(?:\.\d+)?)").unwrap();
+}
+
+/// Reorders retrieved candidates by how relevant they are to a query.
+#[async_trait]
+pub trait Reranker: Debug + Send + Sync {
+    /// Scores the candidates against the query, returning them sorted by their new score.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the candidates could not be scored.
+    async fn rerank(&self, query: &str, candidates: Vec<PointResult>) -> Result<Vec<PointResult>>;
+}
+
+/// Sorts candidates by score, keeping the retrieval order for ties.
+fn sort(mut candidates: Vec<PointResult>) -> Vec<PointResult> {
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates
+}
+
+/// Common words that say nothing about what a query is looking for, so they don't count towards its terms.
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "can", "do", "does", "for", "from", "how",
+    "i", "if", "in", "is", "it", "my", "of", "on", "or", "should", "that", "the", "this", "to",
+    "what", "when", "where", "which", "why", "with", "you", "your",
+];
+
+/// The weight of term overlap used by default, against the retrieval score.
+pub const DEFAULT_LEXICAL_WEIGHT: f32 = 0.5;
+
+/// Scores candidates by the share of the query's terms they contain, blended with their retrieval score.
+#[derive(Debug, Clone, Copy)]
+pub struct LexicalReranker {
+    /// How much term overlap weighs against the retrieval score, from 0 to 1.
+    weight: f32,
+}
+
+impl LexicalReranker {
+    #[must_use]
+    pub const fn new(weight: f32) -> Self {
+        Self { weight }
+    }
+}
+
+impl Default for LexicalReranker {
+    fn default() -> Self {
+        Self::new(DEFAULT_LEXICAL_WEIGHT)
+    }
+}
+
+#[async_trait]
+impl Reranker for LexicalReranker {
+    #[allow(clippy::cast_precision_loss)]
+    async fn rerank(&self, query: &str, candidates: Vec<PointResult>) -> Result<Vec<PointResult>> {
+        let terms = tokenize(query)
+            .into_iter()
+            .filter(|term| !STOPWORDS.contains(&term.as_str()))
+            .collect::<HashSet<_>>();
+        if terms.is_empty() {
+            return Ok(candidates);
+        }
+
+        // Retrieval scores come from different scales depending on the search, so only their relative size is kept.
+        let best = candidates
+            .iter()
+            .map(|candidate| candidate.score)
+            .fold(0.0_f32, f32::max);
+
+        Ok(sort(
+            candidates
+                .into_iter()
+                .map(|candidate| {
+                    let text = tokenize(&format!(
+                        "{} {}",
+                        candidate.payload.title, candidate.payload.text
+                    ))
+                    .into_iter()
+                    .collect::<HashSet<_>>();
+
+                    let overlap = terms.intersection(&text).count() as f32 / terms.len() as f32;
+                    let retrieval = if best > 0.0 {
+                        candidate.score / best
+                    } else {
+                        0.0
+                    };
+
+                    PointResult {
+                        score: self
+                            .weight
+                            .mul_add(overlap, (1.0 - self.weight) * retrieval),
+                        ..candidate
+                    }
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// How many tokens of each passage the chat model is shown, so grading a full page of candidates fits in its context.
+const MAX_PASSAGE_TOKENS: usize = 300;
+
+/// Asks a chat model to grade how well each candidate answers the query.
+#[derive(Debug)]
+pub struct LlmReranker {
+    chat: Arc<dyn ChatModel>,
+    settings: ChatSettings,
+}
+
+impl LlmReranker {
+    #[must_use]
+    pub fn new(chat: Arc<dyn ChatModel>) -> Self {
+        Self {
+            chat,
+            settings: ChatSettings {
+                temperature: 0.0,
+                ..ChatSettings::default()
+            },
+        }
+    }
+
+    fn build_messages(query: &str, candidates: &[PointResult]) -> Vec<ChatMessage> {
+        let passages = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                format!(
+                    "[{i}] {}: {}",
+                    c.payload.title,
+                    tokens::truncate(&c.payload.text, MAX_PASSAGE_TOKENS)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        vec![
+            ChatMessage::new(
+                Role::System,
+                formatdoc!(
+                    "You grade how useful documentation passages are for answering a question.
+                    For every passage, reply with a line in the format `index: score`, where score goes from 0 (irrelevant) to 10 (answers the question). Do not reply with anything else."
+                ),
+            ),
+            ChatMessage::new(
+                Role::User,
+                format!("Question: {query}\n\nPassages:\n{passages}"),
+            ),
+        ]
+    }
+}
+
+#[async_trait]
+impl Reranker for LlmReranker {
+    async fn rerank(&self, query: &str, candidates: Vec<PointResult>) -> Result<Vec<PointResult>> {
+        let grades = self
+            .chat
+            .chat(Self::build_messages(query, &candidates), &self.settings)
+            .await?;
+
+        let mut scores = vec![0.0; candidates.len()];
+        for capture in SCORE_RE.captures_iter(&grades) {
+            if let (Ok(index), Ok(score)) = (capture[1].parse::<usize>(), capture[2].parse()) {
+                if let Some(slot) = scores.get_mut(index) {
+                    *slot = score;
+                }
+            }
+        }
+
+        Ok(sort(
+            candidates
+                .into_iter()
+                .zip(scores)
+                .map(|(candidate, score)| PointResult { score, ..candidate })
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chat::ScriptedChatModel, Payload};
+
+    fn candidate(id: &str, text: &str) -> PointResult {
+        PointResult {
+            id: id.to_string(),
+            score: 1.0,
+            payload: Payload {
+                text: text.to_string(),
+                path: format!("/{id}"),
+                title: String::new(),
+                page_title: String::new(),
+                ..Payload::default()
+            },
+        }
+    }
+
+    fn ids(results: &[PointResult]) -> Vec<&str> {
+        results.iter().map(|r| r.id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn reranks_by_term_overlap() {
+        // Given
+        let candidates = vec![
+            candidate("a", "Install the CLI with cargo."),
+            candidate("b", "Run the embed command to index your docs."),
+            candidate("c", "The embed command skips unchanged sections."),
+        ];
+
+        // When
+        let results = LexicalReranker::default()
+            .rerank("how does the embed command index docs", candidates)
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(ids(&results), vec!["b", "c", "a"]);
+    }
+
+    #[tokio::test]
+    async fn blends_overlap_with_retrieval_scores() {
+        // Given
+        let candidates = vec![
+            PointResult {
+                score: 0.2,
+                ..candidate("a", "How to use the embed command.")
+            },
+            candidate("b", "The embed command skips unchanged sections."),
+            PointResult {
+                score: 0.4,
+                ..candidate("c", "How to use the CLI.")
+            },
+        ];
+
+        // When
+        let results = LexicalReranker::default()
+            .rerank("how to use the embed command", candidates)
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(ids(&results), vec!["b", "a", "c"]);
+    }
+
+    #[tokio::test]
+    async fn reranks_by_model_grades() {
+        // Given
+        let chat = Arc::new(ScriptedChatModel::default().reply(["0: 2\n1: 9\n", "2: 5"]));
+        let candidates = vec![
+            candidate("a", "Install the CLI with cargo."),
+            candidate("b", "Run the embed command to index your docs."),
+            candidate("c", "The embed command skips unchanged sections."),
+        ];
+
+        // When
+        let results = LlmReranker::new(chat.clone())
+            .rerank("How do I index my docs?", candidates)
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(ids(&results), vec!["b", "c", "a"]);
+        assert!(chat.requests()[0][1]
+            .content
+            .contains("[1] : Run the embed command"));
+    }
+
+    #[tokio::test]
+    async fn truncates_passages_graded_by_the_model() {
+        // Given
+        let chat = Arc::new(ScriptedChatModel::default().reply(["0: 5"]));
+        let candidates = vec![candidate("a", &"word ".repeat(MAX_PASSAGE_TOKENS * 2))];
+
+        // When
+        LlmReranker::new(chat.clone())
+            .rerank("How do I index my docs?", candidates)
+            .await
+            .unwrap();
+
+        // Then
+        let prompt = &chat.requests()[0][1].content;
+        assert!(tokens::count(prompt) < MAX_PASSAGE_TOKENS + 50);
+    }
+}
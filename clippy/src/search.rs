@@ -0,0 +1,346 @@
+use anyhow::Result;
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    keyword::KeywordIndexes,
+    rerank::Reranker,
+    store::{Filter, PointResult, VectorStore},
+    Embedder,
+};
+
+/// Dampens the advantage of the top ranks when fusing rankings, as suggested by the original reciprocal rank fusion paper.
+const RRF_K: f32 = 60.0;
+/// How many candidates to fetch from each ranking for every result returned.
+const CANDIDATE_MULTIPLIER: usize = 4;
+
+/// Controls how a project's documentation is searched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchOptions {
+    /// The number of results to return.
+    pub count: usize,
+    /// How much keyword matches weigh against vector similarity, from 0 (vector only) to 1 (keywords only).
+    pub keyword_weight: f32,
+    /// The number of candidates handed to the reranker, if any, before keeping the best `count`.
+    pub candidates: usize,
+    /// Restricts the search to sections matching the filter.
+    pub filter: Filter,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            count: 5,
+            candidates: 20,
+            keyword_weight: 0.3,
+            filter: Filter::default(),
+        }
+    }
+}
+
+/// Retrieves documentation sections relevant to a query, combining vector similarity with keyword matching.
+#[derive(Debug)]
+pub struct Retriever {
+    pub embedder: Arc<dyn Embedder>,
+    pub store: Arc<dyn VectorStore>,
+    keywords: KeywordIndexes,
+    reranker: Option<Arc<dyn Reranker>>,
+}
+
+impl Retriever {
+    #[must_use]
+    pub fn new(embedder: Arc<dyn Embedder>, store: Arc<dyn VectorStore>) -> Self {
+        Self {
+            store,
+            embedder,
+            reranker: None,
+            keywords: KeywordIndexes::default(),
+        }
+    }
+
+    /// Reranks the retrieved candidates with the given reranker before keeping the best ones.
+    #[must_use]
+    pub fn with_reranker(mut self, reranker: Arc<dyn Reranker>) -> Self {
+        self.reranker = Some(reranker);
+        self
+    }
+
+    /// The keyword indexes used for hybrid search, cached per collection.
+    #[must_use]
+    pub const fn keywords(&self) -> &KeywordIndexes {
+        &self.keywords
+    }
+
+    /// Searches a project's documentation.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the vector store, the embedder or the reranker return an error.
+    pub async fn search_project(
+        &self,
+        project_id: &str,
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<Vec<PointResult>> {
+        let vector = self.embedder.raw_embed(query).await?;
+
+        self.search_embedded(project_id, query, vector, options)
+            .await
+    }
+
+    /// Searches a project's documentation, for a query that was already embedded.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the vector store or the reranker return an error.
+    pub async fn search_embedded(
+        &self,
+        project_id: &str,
+        query: &str,
+        vector: Vec<f32>,
+        options: &SearchOptions,
+    ) -> Result<Vec<PointResult>> {
+        let Some(reranker) = &self.reranker else {
+            return self
+                .retrieve(project_id, query, vector, options.count, options)
+                .await;
+        };
+
+        let candidates = self
+            .retrieve(
+                project_id,
+                query,
+                vector,
+                options.candidates.max(options.count),
+                options,
+            )
+            .await?;
+
+        let mut results = reranker.rerank(query, candidates).await?;
+        results.truncate(options.count);
+
+        Ok(results)
+    }
+
+    async fn retrieve(
+        &self,
+        project_id: &str,
+        query: &str,
+        vector: Vec<f32>,
+        count: usize,
+        options: &SearchOptions,
+    ) -> Result<Vec<PointResult>> {
+        let candidates = count * CANDIDATE_MULTIPLIER;
+        let mut vector_results = self
+            .store
+            .query(project_id, vector, candidates, &options.filter)
+            .await?;
+
+        if options.keyword_weight <= 0.0 {
+            vector_results.truncate(count);
+            return Ok(vector_results);
+        }
+
+        let keyword_results = self
+            .keywords
+            .get(self.store.as_ref(), project_id)
+            .await?
+            .search(query, candidates, &options.filter);
+
+        Ok(fuse(
+            &[
+                (1.0 - options.keyword_weight, vector_results),
+                (options.keyword_weight, keyword_results),
+            ],
+            count,
+        ))
+    }
+}
+
+/// Merges weighted rankings with reciprocal rank fusion, returning the `count` best results.
+///
+/// Each result scores `weight / (k + rank)` for every ranking it appears in, which makes rankings with incomparable scores (like cosine similarity and BM25) easy to combine.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn fuse(rankings: &[(f32, Vec<PointResult>)], count: usize) -> Vec<PointResult> {
+    let mut fused: HashMap<&str, PointResult> = HashMap::new();
+
+    for (weight, results) in rankings {
+        for (rank, result) in results.iter().enumerate() {
+            let score = weight / (RRF_K + rank as f32 + 1.0);
+
+            fused
+                .entry(&result.id)
+                .and_modify(|r| r.score += score)
+                .or_insert_with(|| PointResult {
+                    score,
+                    ..result.clone()
+                });
+        }
+    }
+
+    let mut results = fused.into_values().collect::<Vec<_>>();
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    results.truncate(count);
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::PointStruct, HashEmbedder, LocalStore, Payload};
+
+    fn result(id: &str) -> PointResult {
+        PointResult {
+            id: id.to_string(),
+            score: 1.0,
+            payload: Payload {
+                text: String::new(),
+                path: format!("/{id}"),
+                title: String::new(),
+                page_title: String::new(),
+                ..Payload::default()
+            },
+        }
+    }
+
+    fn ids(results: &[PointResult]) -> Vec<&str> {
+        results.iter().map(|r| r.id.as_str()).collect()
+    }
+
+    #[test]
+    fn fuses_rankings_by_weight() {
+        // Given
+        let vector = vec![result("a"), result("b"), result("c")];
+        let keyword = vec![result("c"), result("d")];
+
+        // Then
+        assert_eq!(
+            ids(&fuse(&[(0.5, vector.clone()), (0.5, keyword.clone())], 2)),
+            vec!["c", "a"]
+        );
+        assert_eq!(
+            ids(&fuse(&[(1.0, vector), (0.0, keyword)], 4)),
+            vec!["a", "b", "c", "d"]
+        );
+    }
+
+    #[tokio::test]
+    async fn finds_exact_identifiers() {
+        // Given
+        let embedder = Arc::new(HashEmbedder::new(8));
+        let store = Arc::new(LocalStore::default());
+        store.create_collection("docs", 8).await.unwrap();
+
+        let sections = [
+            ("1", "Configure how and where your documents get stored."),
+            (
+                "2",
+                "Set CLIPPY_STORE_PATH to change where the store is persisted.",
+            ),
+            (
+                "3",
+                "Documents are stored where you configure them to be stored.",
+            ),
+        ];
+
+        let mut points = Vec::new();
+        for (id, text) in sections {
+            points.push(PointStruct {
+                id: id.to_string(),
+                vector: embedder.raw_embed(text).await.unwrap(),
+                payload: Payload {
+                    text: text.to_string(),
+                    ..result(id).payload
+                },
+            });
+        }
+        store.upsert("docs", &points).await.unwrap();
+
+        let retriever = Retriever::new(embedder, store);
+
+        // When
+        let results = retriever
+            .search_project(
+                "docs",
+                "CLIPPY_STORE_PATH",
+                &SearchOptions {
+                    count: 1,
+                    keyword_weight: 0.5,
+                    ..SearchOptions::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(ids(&results), vec!["2"]);
+    }
+
+    #[derive(Debug)]
+    struct ReverseReranker;
+
+    #[async_trait::async_trait]
+    impl Reranker for ReverseReranker {
+        async fn rerank(
+            &self,
+            _: &str,
+            mut candidates: Vec<PointResult>,
+        ) -> Result<Vec<PointResult>> {
+            candidates.reverse();
+            Ok(candidates)
+        }
+    }
+
+    #[tokio::test]
+    async fn reranks_overfetched_candidates() {
+        // Given
+        let embedder = Arc::new(HashEmbedder::new(64));
+        let store = Arc::new(LocalStore::default());
+        store.create_collection("docs", 64).await.unwrap();
+
+        let mut points = Vec::new();
+        for (id, text) in [
+            ("1", "Embed your docs"),
+            ("2", "Ask questions about your docs"),
+            ("3", "Deploy the widget"),
+        ] {
+            points.push(PointStruct {
+                id: id.to_string(),
+                vector: embedder.raw_embed(text).await.unwrap(),
+                payload: Payload {
+                    text: text.to_string(),
+                    ..result(id).payload
+                },
+            });
+        }
+        store.upsert("docs", &points).await.unwrap();
+
+        let options = SearchOptions {
+            count: 1,
+            keyword_weight: 0.0,
+            candidates: 2,
+            ..SearchOptions::default()
+        };
+        let retriever = Retriever::new(embedder, store);
+        let candidates = retriever
+            .retrieve(
+                "docs",
+                "embed docs",
+                retriever.embedder.raw_embed("embed docs").await.unwrap(),
+                2,
+                &options,
+            )
+            .await
+            .unwrap();
+
+        // When
+        let results = retriever
+            .with_reranker(Arc::new(ReverseReranker))
+            .search_project("docs", "embed docs", &options)
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(ids(&results), vec![candidates[1].id.as_str()]);
+    }
+}
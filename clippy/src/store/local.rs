@@ -0,0 +1,593 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+use super::{CollectionInfo, Filter, Payload, PointResult, PointStruct, VectorStore};
+
+/// The file aliases are persisted to, next to the collections.
+const ALIASES_FILE: &str = "_aliases.json";
+
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+struct Collection {
+    dimensions: usize,
+    points: HashMap<String, PointStruct>,
+}
+
+/// An in-process vector store that performs exact (flat) cosine search.
+///
+/// Collections live in memory, and are optionally persisted as JSON files to a directory, which makes it a good fit for tests and small self-hosted installs.
+/// Writes are persisted when the store is flushed or dropped, rather than rewriting the whole collection on every upsert.
+#[derive(Debug, Default)]
+pub struct LocalStore {
+    path: Option<PathBuf>,
+    collections: RwLock<HashMap<String, Collection>>,
+    /// The collection each alias points to.
+    aliases: RwLock<HashMap<String, String>>,
+    /// The collections written to since they were last persisted.
+    dirty: Mutex<HashSet<String>>,
+}
+
+impl LocalStore {
+    /// Opens a store persisted to the given directory, loading any existing collections.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the directory cannot be created or one of its collections cannot be parsed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(&path).context("Failed to create local store directory")?;
+
+        let aliases = match fs::read_to_string(path.join(ALIASES_FILE)) {
+            Ok(aliases) => serde_json::from_str(&aliases).context("Failed to parse aliases")?,
+            Err(err) if err.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut collections = HashMap::new();
+        for entry in fs::read_dir(&path)? {
+            let file = entry?.path();
+            if file.extension().map_or(true, |ext| ext != "json") || file.ends_with(ALIASES_FILE) {
+                continue;
+            }
+
+            let name = file
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| anyhow!("Invalid collection file name"))?
+                .to_string();
+
+            let collection: Collection = serde_json::from_str(&fs::read_to_string(&file)?)
+                .with_context(|| format!("Failed to parse collection {name}"))?;
+
+            collections.insert(name, collection);
+        }
+
+        Ok(Self {
+            path: Some(path),
+            collections: RwLock::new(collections),
+            aliases: RwLock::new(aliases),
+            dirty: Mutex::default(),
+        })
+    }
+
+    /// Returns the name of the collection an alias points to, or the name itself if it isn't an alias.
+    fn resolve(&self, name: &str) -> Result<String> {
+        Ok(self
+            .aliases
+            .read()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string()))
+    }
+
+    fn update_aliases(
+        &self,
+        cb: impl FnOnce(&mut HashMap<String, String>) -> Result<()>,
+    ) -> Result<()> {
+        let mut aliases = self
+            .aliases
+            .write()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?;
+
+        cb(&mut aliases)?;
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        fs::write(path.join(ALIASES_FILE), serde_json::to_string(&*aliases)?)
+            .context("Failed to persist aliases")
+    }
+
+    fn persist(&self, name: &str, collection: &Collection) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        fs::write(
+            path.join(format!("{name}.json")),
+            serde_json::to_string(collection)?,
+        )
+        .with_context(|| format!("Failed to persist collection {name}"))
+    }
+
+    /// Persists the collections written to since the last flush, serializing each one under the read lock and writing it to disk outside of it.
+    fn persist_dirty(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let dirty = std::mem::take(
+            &mut *self
+                .dirty
+                .lock()
+                .map_err(|_| anyhow!("Local store lock poisoned"))?,
+        );
+
+        for name in dirty {
+            let snapshot = {
+                let collections = self
+                    .collections
+                    .read()
+                    .map_err(|_| anyhow!("Local store lock poisoned"))?;
+
+                match collections.get(&name) {
+                    Some(collection) => serde_json::to_string(collection)?,
+                    None => continue,
+                }
+            };
+
+            if let Err(err) = fs::write(path.join(format!("{name}.json")), snapshot) {
+                self.mark_dirty(&name)?;
+                return Err(err).with_context(|| format!("Failed to persist collection {name}"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mark_dirty(&self, name: &str) -> Result<()> {
+        self.dirty
+            .lock()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?
+            .insert(name.to_string());
+
+        Ok(())
+    }
+
+    fn with_collection<T>(
+        &self,
+        name: &str,
+        cb: impl FnOnce(&mut Collection) -> Result<T>,
+    ) -> Result<T> {
+        let name = &self.resolve(name)?;
+        let mut collections = self
+            .collections
+            .write()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?;
+
+        let collection = collections
+            .get_mut(name)
+            .ok_or_else(|| anyhow!("Collection {name} does not exist"))?;
+
+        let result = cb(collection)?;
+        drop(collections);
+        self.mark_dirty(name)?;
+
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl VectorStore for LocalStore {
+    async fn create_collection(&self, name: &str, dimensions: usize) -> Result<()> {
+        let collection = Collection {
+            dimensions,
+            points: HashMap::new(),
+        };
+
+        self.persist(name, &collection)?;
+        self.collections
+            .write()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?
+            .insert(name.to_string(), collection);
+
+        Ok(())
+    }
+
+    async fn delete_collection(&self, name: &str) -> Result<()> {
+        self.collections
+            .write()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?
+            .remove(name);
+        self.dirty
+            .lock()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?
+            .remove(name);
+
+        // Like Qdrant, aliases don't outlive the collection they point to.
+        let aliased = self
+            .aliases
+            .read()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?
+            .values()
+            .any(|collection| collection == name);
+        if aliased {
+            self.update_aliases(|aliases| {
+                aliases.retain(|_, collection| collection != name);
+                Ok(())
+            })?;
+        }
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        match fs::remove_file(path.join(format!("{name}.json"))) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Failed to delete collection {name}"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn collection_exists(&self, name: &str) -> Result<bool> {
+        Ok(self
+            .collections
+            .read()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?
+            .contains_key(name))
+    }
+
+    async fn collection_info(&self, name: &str) -> Result<CollectionInfo> {
+        let name = &self.resolve(name)?;
+        let collections = self
+            .collections
+            .read()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?;
+
+        let collection = collections
+            .get(name)
+            .ok_or_else(|| anyhow!("Collection {name} does not exist"))?;
+
+        Ok(CollectionInfo {
+            points: collection.points.len(),
+            dimensions: collection.dimensions,
+        })
+    }
+
+    async fn alias(&self, alias: &str) -> Result<Option<String>> {
+        Ok(self
+            .aliases
+            .read()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?
+            .get(alias)
+            .cloned())
+    }
+
+    async fn set_alias(&self, alias: &str, collection: &str) -> Result<()> {
+        if !self.collection_exists(collection).await? {
+            return Err(anyhow!("Collection {collection} does not exist"));
+        }
+
+        self.update_aliases(|aliases| {
+            aliases.insert(alias.to_string(), collection.to_string());
+            Ok(())
+        })
+    }
+
+    async fn delete_alias(&self, alias: &str) -> Result<()> {
+        self.update_aliases(|aliases| {
+            aliases
+                .remove(alias)
+                .map(|_| ())
+                .ok_or_else(|| anyhow!("Alias {alias} does not exist"))
+        })
+    }
+
+    async fn upsert(&self, collection: &str, points: &[PointStruct]) -> Result<()> {
+        self.with_collection(collection, |collection| {
+            for point in points {
+                if point.vector.len() != collection.dimensions {
+                    return Err(anyhow!(
+                        "Expected a vector of {} dimensions, got {}",
+                        collection.dimensions,
+                        point.vector.len()
+                    ));
+                }
+
+                collection.points.insert(point.id.clone(), point.clone());
+            }
+
+            Ok(())
+        })
+    }
+
+    async fn query(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        count: usize,
+        filter: &Filter,
+    ) -> Result<Vec<PointResult>> {
+        let collection = &self.resolve(collection)?;
+        let collections = self
+            .collections
+            .read()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?;
+
+        let collection = collections
+            .get(collection)
+            .ok_or_else(|| anyhow!("Collection {collection} does not exist"))?;
+
+        let mut results = collection
+            .points
+            .values()
+            .filter(|point| filter.matches(&point.payload))
+            .map(|point| PointResult {
+                id: point.id.clone(),
+                score: cosine_similarity(&vector, &point.vector),
+                payload: point.payload.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(count);
+
+        Ok(results)
+    }
+
+    async fn ids(&self, collection: &str) -> Result<Vec<String>> {
+        let collection = &self.resolve(collection)?;
+        let collections = self
+            .collections
+            .read()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?;
+
+        Ok(collections
+            .get(collection)
+            .ok_or_else(|| anyhow!("Collection {collection} does not exist"))?
+            .points
+            .keys()
+            .cloned()
+            .collect())
+    }
+
+    async fn payloads(&self, collection: &str) -> Result<Vec<(String, Payload)>> {
+        let collection = &self.resolve(collection)?;
+        let collections = self
+            .collections
+            .read()
+            .map_err(|_| anyhow!("Local store lock poisoned"))?;
+
+        Ok(collections
+            .get(collection)
+            .ok_or_else(|| anyhow!("Collection {collection} does not exist"))?
+            .points
+            .values()
+            .map(|point| (point.id.clone(), point.payload.clone()))
+            .collect())
+    }
+
+    async fn delete(&self, collection: &str, ids: &[String]) -> Result<()> {
+        self.with_collection(collection, |collection| {
+            for id in ids {
+                collection.points.remove(id);
+            }
+
+            Ok(())
+        })
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.persist_dirty()
+    }
+}
+
+impl Drop for LocalStore {
+    fn drop(&mut self) {
+        if let Err(err) = self.persist_dirty() {
+            tracing::error!("Failed to persist local store: {err:#}");
+        }
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let norm =
+        a.iter().map(|v| v * v).sum::<f32>().sqrt() * b.iter().map(|v| v * v).sum::<f32>().sqrt();
+
+    if norm == 0.0 {
+        0.0
+    } else {
+        dot / norm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(id: &str, vector: Vec<f32>) -> PointStruct {
+        PointStruct {
+            vector,
+            id: id.to_string(),
+            payload: Payload {
+                text: format!("Section {id}"),
+                path: format!("/{id}"),
+                title: String::new(),
+                page_title: String::new(),
+                ..Payload::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn returns_closest_points_first() {
+        // Given
+        let store = LocalStore::default();
+        store.create_collection("docs", 2).await.unwrap();
+        store
+            .upsert(
+                "docs",
+                &[
+                    point("a", vec![1.0, 0.0]),
+                    point("b", vec![0.0, 1.0]),
+                    point("c", vec![0.7, 0.7]),
+                ],
+            )
+            .await
+            .unwrap();
+
+        // When
+        let results = store
+            .query("docs", vec![1.0, 0.1], 2, &Filter::default())
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(
+            results.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(),
+            vec!["a", "c"]
+        );
+    }
+
+    #[tokio::test]
+    async fn deletes_points() {
+        // Given
+        let store = LocalStore::default();
+        store.create_collection("docs", 2).await.unwrap();
+        store
+            .upsert(
+                "docs",
+                &[point("a", vec![1.0, 0.0]), point("b", vec![0.0, 1.0])],
+            )
+            .await
+            .unwrap();
+
+        // When
+        store.delete("docs", &["a".to_string()]).await.unwrap();
+
+        // Then
+        let results = store
+            .query("docs", vec![1.0, 0.0], 10, &Filter::default())
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "b");
+    }
+
+    #[tokio::test]
+    async fn rejects_vectors_of_the_wrong_size() {
+        // Given
+        let store = LocalStore::default();
+        store.create_collection("docs", 3).await.unwrap();
+
+        // When
+        let err = store
+            .upsert("docs", &[point("a", vec![1.0, 0.0])])
+            .await
+            .unwrap_err();
+
+        // Then
+        assert_eq!(err.to_string(), "Expected a vector of 3 dimensions, got 2");
+    }
+
+    #[tokio::test]
+    async fn swaps_aliases_between_collections() {
+        // Given
+        let store = LocalStore::default();
+        store.create_collection("docs_1", 2).await.unwrap();
+        store.create_collection("docs_2", 2).await.unwrap();
+        store
+            .upsert("docs_2", &[point("a", vec![1.0, 0.0])])
+            .await
+            .unwrap();
+        store.set_alias("docs", "docs_1").await.unwrap();
+
+        // When
+        store.set_alias("docs", "docs_2").await.unwrap();
+        store.delete_collection("docs_1").await.unwrap();
+
+        // Then
+        assert_eq!(
+            store.alias("docs").await.unwrap().as_deref(),
+            Some("docs_2")
+        );
+        assert_eq!(
+            store.collection_info("docs").await.unwrap(),
+            CollectionInfo {
+                points: 1,
+                dimensions: 2
+            }
+        );
+        assert!(!store.collection_exists("docs").await.unwrap());
+        assert!(!store.collection_exists("docs_1").await.unwrap());
+        assert!(store.set_alias("docs", "docs_1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn deletes_aliases_with_their_collection() {
+        // Given
+        let store = LocalStore::default();
+        store.create_collection("docs_1", 2).await.unwrap();
+        store.set_alias("docs", "docs_1").await.unwrap();
+
+        // When
+        store.delete_collection("docs_1").await.unwrap();
+
+        // Then
+        assert_eq!(store.alias("docs").await.unwrap(), None);
+        assert!(store.collection_info("docs").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn persists_collections_when_flushed() {
+        // Given
+        let dir = std::env::temp_dir().join(format!("clippy-store-{}", std::process::id()));
+        let store = LocalStore::open(&dir).unwrap();
+        store.create_collection("docs", 2).await.unwrap();
+        store
+            .upsert("docs", &[point("a", vec![1.0, 0.0])])
+            .await
+            .unwrap();
+        let before = LocalStore::open(&dir)
+            .unwrap()
+            .collection_info("docs")
+            .await
+            .unwrap();
+
+        // When
+        store.flush().await.unwrap();
+
+        // Then
+        let after = LocalStore::open(&dir)
+            .unwrap()
+            .collection_info("docs")
+            .await
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(before.points, 0);
+        assert_eq!(after.points, 1);
+    }
+
+    #[tokio::test]
+    async fn fails_on_missing_collections() {
+        // When
+        let err = LocalStore::default()
+            .query("docs", vec![1.0], 1, &Filter::default())
+            .await
+            .unwrap_err();
+
+        // Then
+        assert_eq!(err.to_string(), "Collection docs does not exist");
+    }
+}
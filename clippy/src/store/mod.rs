@@ -0,0 +1,270 @@
+mod local;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use std::{collections::HashMap, env, fmt::Debug, sync::Arc};
+
+pub use local::LocalStore;
+
+use crate::Qdrant;
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize, opg::OpgModel)]
+pub struct Payload {
+    pub text: String,
+    pub path: String,
+    pub title: String,
+    pub page_title: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// Restricts a search to the points whose payload matches every condition set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct Filter {
+    /// Only match pages under this path, like `/api/`. Prefixes are matched on whole path segments.
+    pub path_prefix: Option<String>,
+    pub page_title: Option<String>,
+    /// Metadata values the payload must have, like `version: v2`.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Tags the payload must all have.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Filter {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    #[must_use]
+    pub fn matches(&self, payload: &Payload) -> bool {
+        self.path_prefix
+            .as_ref()
+            .map_or(true, |prefix| path_prefixes(&payload.path).contains(prefix))
+            && self
+                .page_title
+                .as_ref()
+                .map_or(true, |title| &payload.page_title == title)
+            && self
+                .metadata
+                .iter()
+                .all(|(key, value)| payload.metadata.get(key) == Some(value))
+            && self.tags.iter().all(|tag| payload.tags.contains(tag))
+    }
+}
+
+/// Lists the prefixes of a path that end on a segment boundary, with and without a trailing slash.
+///
+/// `/api/v2` has the prefixes `/`, `/api`, `/api/`, `/api/v2`.
+#[must_use]
+pub fn path_prefixes(path: &str) -> Vec<String> {
+    let mut prefixes = vec!["/".to_string()];
+
+    for (i, _) in path.match_indices('/').skip(1) {
+        prefixes.push(path[..i].to_string());
+        prefixes.push(path[..=i].to_string());
+    }
+
+    if path != "/" && !path.ends_with('/') {
+        prefixes.push(path.to_string());
+    }
+
+    prefixes
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct PointStruct {
+    pub id: String,
+    pub vector: Vec<f32>,
+    pub payload: Payload,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct PointResult {
+    pub id: String,
+    pub score: f32,
+    pub payload: Payload,
+}
+
+/// The size of a collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct CollectionInfo {
+    pub points: usize,
+    pub dimensions: usize,
+}
+
+/// A storage backend for embedded documentation sections.
+///
+/// Collections can also be reached through aliases, which let a new collection be built in the background and swapped in at once.
+#[async_trait]
+pub trait VectorStore: Debug + Send + Sync {
+    /// Creates a new collection, sized for vectors of the given dimensions.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection could not be created.
+    async fn create_collection(&self, name: &str, dimensions: usize) -> Result<()>;
+
+    /// Deletes a collection along with its points. Deleting a collection that doesn't exist does nothing.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection could not be deleted.
+    async fn delete_collection(&self, name: &str) -> Result<()>;
+
+    /// Checks whether a collection exists. Aliases don't count as collections.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collections could not be listed.
+    async fn collection_exists(&self, name: &str) -> Result<bool>;
+
+    /// Returns the number of points in a collection, and the size of its vectors.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection does not exist or could not be read.
+    async fn collection_info(&self, name: &str) -> Result<CollectionInfo>;
+
+    /// Returns the name of the collection an alias points to, if the alias exists.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the aliases could not be listed.
+    async fn alias(&self, alias: &str) -> Result<Option<String>>;
+
+    /// Points an alias to a collection, creating it or atomically moving it from the collection it pointed to before.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection does not exist or the alias could not be updated.
+    async fn set_alias(&self, alias: &str, collection: &str) -> Result<()>;
+
+    /// Deletes an alias, leaving the collection it points to untouched.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the alias does not exist or could not be deleted.
+    async fn delete_alias(&self, alias: &str) -> Result<()>;
+
+    /// Inserts the given points into a collection, replacing any existing points with the same id.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection does not exist or the points could not be stored.
+    async fn upsert(&self, collection: &str, points: &[PointStruct]) -> Result<()>;
+
+    /// Returns the `count` points closest to the given vector, among the ones matching the filter.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection does not exist or could not be searched.
+    async fn query(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        count: usize,
+        filter: &Filter,
+    ) -> Result<Vec<PointResult>>;
+
+    /// Lists the ids of every point in a collection.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection does not exist or could not be read.
+    async fn ids(&self, collection: &str) -> Result<Vec<String>>;
+
+    /// Lists the id and payload of every point in a collection.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection does not exist or could not be read.
+    async fn payloads(&self, collection: &str) -> Result<Vec<(String, Payload)>>;
+
+    /// Removes the points with the given ids from a collection.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the collection does not exist or the points could not be removed.
+    async fn delete(&self, collection: &str, ids: &[String]) -> Result<()>;
+
+    /// Persists the points written since the last flush, for stores that buffer them. Indexing calls this once it is done writing to a collection.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the points could not be persisted.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Builds the vector store configured through the environment.
+///
+/// Uses Qdrant when `$QDRANT_URL` is set, and otherwise a local store persisted to `$LOCAL_STORE_PATH`. One of them has to be set, as an in-memory store would lose every index on restart.
+///
+/// # Errors
+///
+/// This function will return an error if neither variable is set, the Qdrant options are invalid, or the local store could not be loaded from disk.
+pub fn from_env() -> Result<Arc<dyn VectorStore>> {
+    let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+
+    if var("QDRANT_URL").is_some() {
+        return Ok(Arc::new(Qdrant::from_env()?));
+    }
+
+    match var("LOCAL_STORE_PATH") {
+        Some(path) => Ok(Arc::new(LocalStore::open(path)?)),
+        None => bail!("Either $QDRANT_URL or $LOCAL_STORE_PATH must be set"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_path_prefixes() {
+        assert_eq!(
+            path_prefixes("/api/v2/auth"),
+            vec!["/", "/api", "/api/", "/api/v2", "/api/v2/", "/api/v2/auth"]
+        );
+        assert_eq!(path_prefixes("/"), vec!["/"]);
+    }
+
+    #[test]
+    fn matches_payloads() {
+        // Given
+        let payload = Payload {
+            text: "Authenticate with an API key".to_string(),
+            path: "/api/auth".to_string(),
+            title: "Authentication".to_string(),
+            page_title: "API".to_string(),
+            metadata: HashMap::from([("version".to_string(), "v2".to_string())]),
+            tags: vec!["security".to_string()],
+        };
+
+        // Then
+        assert!(Filter::default().matches(&payload));
+        assert!(Filter {
+            path_prefix: Some("/api/".to_string()),
+            metadata: HashMap::from([("version".to_string(), "v2".to_string())]),
+            tags: vec!["security".to_string()],
+            ..Filter::default()
+        }
+        .matches(&payload));
+        assert!(!Filter {
+            path_prefix: Some("/ap".to_string()),
+            ..Filter::default()
+        }
+        .matches(&payload));
+        assert!(!Filter {
+            metadata: HashMap::from([("version".to_string(), "v1".to_string())]),
+            ..Filter::default()
+        }
+        .matches(&payload));
+    }
+}
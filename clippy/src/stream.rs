@@ -1,63 +1,309 @@
+use anyhow::Result;
 use async_fn_stream::try_fn_stream;
-use async_openai::{error::OpenAIError, types::CreateChatCompletionStreamResponse};
 use futures::{Stream, StreamExt};
+use std::sync::Arc;
 
 use crate::{
+    budget::{self, ContextBudget, ContextReport},
     build_messages,
-    qdrant::{Payload, PointResult},
-    OpenAI, Qdrant,
+    cache::AnswerCache,
+    chat::{ChatMessage, ChatModel, ChatSettings},
+    citations::{self, Citation},
+    langchain::{build_condense_messages, trim_history},
+    PromptTemplate, Retriever, SearchOptions,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PartialResult {
     Error(String),
     PartialAnswer(String),
-    References(Vec<Payload>),
+    /// The passages retrieved for the question, and which of them fit in the prompt.
+    Context(ContextReport),
+    /// The sources cited by the answer, sent once it is complete.
+    Citations(Vec<Citation>),
 }
 
-impl From<Result<CreateChatCompletionStreamResponse, OpenAIError>> for PartialResult {
-    fn from(answer: Result<CreateChatCompletionStreamResponse, OpenAIError>) -> Self {
+impl From<Result<String>> for PartialResult {
+    fn from(answer: Result<String>) -> Self {
         match answer {
-            Ok(res) => Self::PartialAnswer(
-                res.choices
-                    .into_iter()
-                    .filter_map(|c| c.delta.content)
-                    .collect(),
-            ),
+            Ok(delta) => Self::PartialAnswer(delta),
             Err(e) => Self::Error(e.to_string()),
         }
     }
 }
 
-impl From<&Vec<PointResult>> for PartialResult {
-    fn from(results: &Vec<PointResult>) -> Self {
-        Self::References(results.iter().map(|p| p.payload.clone()).collect())
-    }
+/// The number of tokens of conversation history kept when answering a follow-up question.
+pub const MAX_HISTORY_TOKENS: usize = 1_000;
+
+/// A question about a project's documentation, along with everything needed to answer it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Question {
+    pub project_id: String,
+    /// The name of the project, as the assistant should refer to it.
+    pub project_name: String,
+    pub query: String,
+    /// The previous turns of the conversation, oldest first.
+    pub history: Vec<ChatMessage>,
+    pub settings: ChatSettings,
+    pub search: SearchOptions,
+    pub prompt: PromptTemplate,
+    pub budget: ContextBudget,
 }
 
+/// Answers a question, streaming the context it was given, the answer and its citations.
+///
+/// Standalone questions similar enough to one answered before replay the cached answer, if a cache is given along with the generation of the project's index, like when it was last trained. Answers given from other generations are never replayed.
 pub fn ask(
-    project_id: String,
-    query: String,
+    retriever: Arc<Retriever>,
+    chat: Arc<dyn ChatModel>,
+    cache: Option<(Arc<AnswerCache>, String)>,
+    question: Question,
 ) -> impl Stream<Item = std::result::Result<PartialResult, anyhow::Error>> {
-    try_fn_stream(|emitter| async move {
-        let client = OpenAI::new();
-        let query_points = client.raw_embed(&query).await?;
-
-        let qdrant = Qdrant::new().collection(&project_id);
-        let results = qdrant.query(query_points, 3).await?;
-        emitter.emit((&results).into()).await;
-
-        let mut answer_stream = client
-            .chat_stream(build_messages(
-                &query,
-                &results.iter().map(Into::into).collect::<Vec<_>>(),
-            ))
+    try_fn_stream(move |emitter| async move {
+        let history = trim_history(&question.history, MAX_HISTORY_TOKENS);
+
+        // Follow-up questions often only make sense within the conversation, so rephrase them before searching.
+        let search_query = if history.is_empty() {
+            question.query.clone()
+        } else {
+            chat.chat(
+                build_condense_messages(&question.query, history),
+                &question.settings,
+            )
+            .await?
+        };
+
+        let vector = retriever.embedder.raw_embed(&search_query).await?;
+
+        // Answers to follow-up questions depend on the conversation, so only standalone ones are cached.
+        let cache = cache.filter(|_| history.is_empty());
+        if let Some(events) = cache
+            .as_ref()
+            .and_then(|(cache, generation)| cache.get(&question, &vector, generation))
+        {
+            for event in events {
+                emitter.emit(event).await;
+            }
+
+            return Ok(());
+        }
+
+        let results = retriever
+            .search_embedded(
+                &question.project_id,
+                &search_query,
+                vector.clone(),
+                &question.search,
+            )
+            .await?;
+
+        let context = budget::assemble(results, &question.budget);
+        let mut events = vec![PartialResult::Context(context.report.clone())];
+        emitter.emit(events[0].clone()).await;
+
+        let mut answer_stream = chat
+            .chat_stream(
+                build_messages(
+                    &question.prompt,
+                    &question.project_name,
+                    &question.query,
+                    &context.contexts,
+                    history,
+                ),
+                &question.settings,
+            )
             .await?;
 
+        let mut answer = String::new();
+        let mut failed = false;
         while let Some(response) = answer_stream.next().await {
-            emitter.emit(response.into()).await;
+            match &response {
+                Ok(delta) => answer.push_str(delta),
+                Err(_) => failed = true,
+            }
+
+            let event = PartialResult::from(response);
+            events.push(event.clone());
+            emitter.emit(event).await;
+        }
+
+        let citations = PartialResult::Citations(citations::parse(&answer, &context.sources));
+        events.push(citations.clone());
+        emitter.emit(citations).await;
+
+        if let (Some((cache, generation)), false) = (cache, failed) {
+            cache.insert(&question, vector, generation, events);
         }
 
         Ok(())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chat::{Role, ScriptedChatModel},
+        Embedder, HashEmbedder, LocalStore, Payload, VectorStore,
+    };
+
+    fn question(query: &str, history: Vec<ChatMessage>) -> Question {
+        Question {
+            history,
+            query: query.to_string(),
+            project_id: "docs".to_string(),
+            project_name: "Clippy".to_string(),
+            settings: ChatSettings::default(),
+            search: SearchOptions::default(),
+            prompt: PromptTemplate::default(),
+            budget: ContextBudget::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_answer_and_citations() {
+        // Given
+        let embedder = Arc::new(HashEmbedder::new(64));
+        let store = Arc::new(LocalStore::default());
+        let chat = Arc::new(ScriptedChatModel::default().reply(["Run ", "`clippy embed` [1]."]));
+
+        store.create_collection("docs", 64).await.unwrap();
+        store
+            .upsert(
+                "docs",
+                &[crate::store::PointStruct {
+                    id: "1".to_string(),
+                    vector: embedder.raw_embed("How to embed docs").await.unwrap(),
+                    payload: Payload {
+                        path: "/embed".to_string(),
+                        title: "Embedding".to_string(),
+                        page_title: "CLI".to_string(),
+                        text: "Run clippy embed to index your docs.".to_string(),
+                        ..Payload::default()
+                    },
+                }],
+            )
+            .await
+            .unwrap();
+
+        // When
+        let events = ask(
+            Arc::new(Retriever::new(embedder, store)),
+            chat.clone(),
+            None,
+            question("How do I embed docs?", Vec::new()),
+        )
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+
+        // Then
+        assert!(
+            matches!(&events[0], PartialResult::Context(report) if report.passages[0].path == "/embed")
+        );
+        assert!(matches!(&events[1], PartialResult::PartialAnswer(a) if a == "Run "));
+        assert!(
+            matches!(&events[2], PartialResult::PartialAnswer(a) if a == "`clippy embed` [1].")
+        );
+        assert!(matches!(
+            &events[3],
+            PartialResult::Citations(citations) if citations[0].source.path == "/embed" && citations[0].spans.first() == Some(&(19..22))
+        ));
+
+        let requests = chat.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0][0].content.contains(
+            "[1] Title: CLI\nPath: /embed\nContent:Run clippy embed to index your docs."
+        ));
+        assert_eq!(requests[0][1].content, "How do I embed docs?");
+    }
+
+    #[tokio::test]
+    async fn condenses_follow_up_questions() {
+        // Given
+        let embedder = Arc::new(HashEmbedder::new(64));
+        let store = Arc::new(LocalStore::default());
+        let chat = Arc::new(
+            ScriptedChatModel::default()
+                .reply(["How do I embed docs with TypeScript?"])
+                .reply(["Use the SDK."]),
+        );
+        store.create_collection("docs", 64).await.unwrap();
+
+        let history = vec![
+            ChatMessage::new(Role::User, "How do I embed docs?"),
+            ChatMessage::new(Role::Assistant, "Run `clippy embed`."),
+        ];
+
+        // When
+        ask(
+            Arc::new(Retriever::new(embedder, store)),
+            chat.clone(),
+            None,
+            question("And in TypeScript?", history.clone()),
+        )
+        .collect::<Vec<_>>()
+        .await;
+
+        // Then
+        let requests = chat.requests();
+        assert!(requests[0][1]
+            .content
+            .contains("Assistant: Run `clippy embed`."));
+        assert!(requests[0][1]
+            .content
+            .contains("Follow-up question: And in TypeScript?"));
+        assert_eq!(&requests[1][1..3], &history[..]);
+        assert_eq!(requests[1][3].content, "And in TypeScript?");
+    }
+
+    #[tokio::test]
+    async fn replays_cached_answers() {
+        // Given
+        let embedder = Arc::new(HashEmbedder::new(64));
+        let store = Arc::new(LocalStore::default());
+        let chat = Arc::new(ScriptedChatModel::default().reply(["Run ", "`clippy embed`."]));
+        let retriever = Arc::new(Retriever::new(embedder, store.clone()));
+        let cache = Arc::new(AnswerCache::default());
+        store.create_collection("docs", 64).await.unwrap();
+
+        ask(
+            retriever.clone(),
+            chat.clone(),
+            Some((cache.clone(), "1".to_string())),
+            question("How do I embed docs?", Vec::new()),
+        )
+        .collect::<Vec<_>>()
+        .await;
+
+        // When
+        let events = ask(
+            retriever.clone(),
+            chat.clone(),
+            Some((cache.clone(), "1".to_string())),
+            question("How do I embed docs?", Vec::new()),
+        )
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+        ask(
+            retriever,
+            chat.clone(),
+            Some((cache, "2".to_string())),
+            question("How do I embed docs?", Vec::new()),
+        )
+        .collect::<Vec<_>>()
+        .await;
+
+        // Then
+        assert_eq!(chat.requests().len(), 2);
+        assert!(matches!(&events[0], PartialResult::Context(_)));
+        assert!(matches!(&events[1], PartialResult::PartialAnswer(a) if a == "Run "));
+        assert!(matches!(&events[2], PartialResult::PartialAnswer(a) if a == "`clippy embed`."));
+        assert!(matches!(&events[3], PartialResult::Citations(c) if c.is_empty()));
+    }
+}
@@ -0,0 +1,53 @@
+use lazy_static::lazy_static;
+use tiktoken_rs::{cl100k_base, CoreBPE};
+
+lazy_static! {
+    /// The tokenizer used by both `text-embedding-ada-002` and the `gpt-3.5-turbo` family of models.
+    static ref TOKENIZER: CoreBPE = cl100k_base().unwrap();
+}
+
+/// Counts the number of tokens in a string.
+#[must_use]
+pub fn count(text: &str) -> usize {
+    TOKENIZER.encode_with_special_tokens(text).len()
+}
+
+/// Truncates a string so it doesn't exceed the given number of tokens.
+#[must_use]
+pub fn truncate(text: &str, max_tokens: usize) -> String {
+    let tokens = TOKENIZER.encode_with_special_tokens(text);
+    if tokens.len() <= max_tokens {
+        return text.to_string();
+    }
+
+    // A token boundary might fall in the middle of a multi-byte character, in which case we drop tokens until it decodes.
+    (0..=max_tokens)
+        .rev()
+        .find_map(|end| TOKENIZER.decode(tokens[..end].to_vec()).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_tokens() {
+        assert_eq!(count("hello world"), 2);
+        assert_eq!(count(""), 0);
+    }
+
+    #[test]
+    fn truncates_to_token_limit() {
+        // When
+        let truncated = truncate("The quick brown fox jumped over the lazy dog", 4);
+
+        // Then
+        assert_eq!(truncated, "The quick brown fox");
+    }
+
+    #[test]
+    fn leaves_short_text_untouched() {
+        assert_eq!(truncate("The quick brown fox", 100), "The quick brown fox");
+    }
+}
@@ -0,0 +1,504 @@
+use anyhow::{anyhow, bail, Context, Result};
+use crawler::{Config, Website};
+pub use crawler::is_public;
+use glob::{MatchOptions, Pattern};
+use html2md::parse_html;
+use readability::extractor::extract;
+use reqwest::Client;
+use std::{
+    fs::{self, DirEntry},
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+};
+use tracing::{debug, warn};
+
+use crate::{into_document, ChunkConfig, Document};
+
+/// The extensions of the files documentation is parsed from.
+const EXTENSIONS: [&str; 2] = ["md", "mdx"];
+
+fn default_branch() -> String {
+    "main".to_string()
+}
+
+/// What kind of source documentation comes from, along with the settings specific to it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SourceKind {
+    /// The markdown files of a GitHub repository, like `owner/repo`.
+    GitHub {
+        repo: String,
+        #[serde(default = "default_branch")]
+        branch: String,
+        /// The directory of the repository the documentation lives in, like `docs`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+    /// The pages of a website, crawled from a base URL.
+    Website {
+        url: String,
+        /// The number of pages to crawl at most.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_pages: Option<usize>,
+    },
+    /// A zip archive of markdown files, stored on disk.
+    Archive { path: PathBuf },
+}
+
+/// Where a project's documentation comes from, and which of its files to keep.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct Source {
+    #[serde(flatten)]
+    pub kind: SourceKind,
+    /// Globs the path of a file has to match one of to be kept, like `guides/**`. Every file is kept when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Globs the path of a file can't match any of to be kept, like `**/changelog.md`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+impl From<SourceKind> for Source {
+    fn from(kind: SourceKind) -> Self {
+        Self {
+            kind,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl Source {
+    /// Makes sure the globs are valid and the repository path stays within the repository.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error describing the first invalid setting.
+    pub fn validate(&self) -> Result<()> {
+        for glob in self.include.iter().chain(&self.exclude) {
+            Pattern::new(glob).with_context(|| format!("Invalid glob `{glob}`"))?;
+        }
+
+        if let SourceKind::GitHub {
+            path: Some(path), ..
+        } = &self.kind
+        {
+            if !Path::new(path)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::RootDir))
+            {
+                bail!("Invalid repository path `{path}`");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Identifies the source within its project, like `owner/repo/main/docs` for a directory of a repository, so the documents of different sources never share a path.
+    #[must_use]
+    pub fn slug(&self) -> String {
+        match &self.kind {
+            SourceKind::GitHub { repo, branch, path } => path.as_deref().map_or_else(
+                || format!("{repo}/{branch}"),
+                |path| format!("{repo}/{branch}/{}", path.trim_matches('/')),
+            ),
+            SourceKind::Website { url, .. } => {
+                let url = url.split_once("://").map_or(url.as_str(), |(_, url)| url);
+                url.trim_end_matches('/').to_string()
+            }
+            SourceKind::Archive { path } => path
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().to_string()),
+        }
+    }
+
+    /// Downloads the documentation into a directory, keeping only the files it can be parsed from, and returns the directory it lives in.
+    ///
+    /// Websites are only crawled from hosts that resolve to public addresses when `public_only` is set, like for sources added through the API.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the settings are invalid, or the documentation could not be downloaded or written to the directory.
+    pub async fn download(&self, dir: &Path, public_only: bool) -> Result<PathBuf> {
+        self.validate()?;
+        fs::create_dir_all(dir).context("Failed to create directory")?;
+
+        let root = match &self.kind {
+            SourceKind::GitHub { repo, branch, path } => {
+                fetch_repo(repo, branch, dir).await?;
+                path.as_ref().map_or_else(
+                    || dir.to_path_buf(),
+                    |path| dir.join(path.trim_matches('/')),
+                )
+            }
+            SourceKind::Website { url, max_pages } => {
+                crawl_website(url, *max_pages, public_only, dir).await?;
+                dir.to_path_buf()
+            }
+            SourceKind::Archive { path } => {
+                let archive = fs::read(path).context("Failed to read archive")?;
+                zip_extract::extract(Cursor::new(archive), dir, true)
+                    .context("Failed to extract zip file")?;
+                dir.to_path_buf()
+            }
+        };
+
+        self.remove_unwanted_files(&root)?;
+
+        Ok(root)
+    }
+
+    /// Removes the files documentation can't be parsed from, and the ones the globs leave out.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a glob is invalid, or the directory could not be read or a file could not be removed.
+    pub fn remove_unwanted_files(&self, root: &Path) -> Result<()> {
+        let patterns = |globs: &[String]| -> Result<Vec<Pattern>> {
+            globs
+                .iter()
+                .map(|glob| Pattern::new(glob).with_context(|| format!("Invalid glob `{glob}`")))
+                .collect()
+        };
+        let include = patterns(&self.include)?;
+        let exclude = patterns(&self.exclude)?;
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::default()
+        };
+
+        remove_unsupported_files(root)?;
+
+        for file in read_dir_recursive(root)? {
+            let path = file.path();
+            let relative = path.strip_prefix(root)?;
+
+            let included = include.is_empty()
+                || include
+                    .iter()
+                    .any(|p| p.matches_path_with(relative, options));
+            let excluded = exclude
+                .iter()
+                .any(|p| p.matches_path_with(relative, options));
+
+            if !included || excluded {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Narrows a repository downloaded into `root` to the files changed in it, given as paths relative to the repository, like `docs/install.md`.
+    ///
+    /// Returns the paths of the documents whose files were changed but are gone, because they were removed or the globs leave them out.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the directory could not be read or a file could not be removed.
+    pub fn retain_changed(&self, root: &Path, changed: &[String]) -> Result<Vec<String>> {
+        let prefix = match &self.kind {
+            SourceKind::GitHub {
+                path: Some(path), ..
+            } => PathBuf::from(path.trim_matches('/')),
+            _ => PathBuf::new(),
+        };
+        let changed = changed
+            .iter()
+            .filter_map(|path| Path::new(path).strip_prefix(&prefix).ok())
+            .filter(|path| is_supported(path))
+            .collect::<Vec<_>>();
+
+        for file in read_dir_recursive(root)? {
+            let path = file.path();
+
+            if !changed.contains(&path.strip_prefix(root)?) {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        Ok(changed
+            .into_iter()
+            .filter(|path| !root.join(path).exists())
+            .filter_map(|path| {
+                path.with_extension("")
+                    .to_str()
+                    .map(|path| format!("/{path}"))
+            })
+            .collect())
+    }
+}
+
+/// Downloads and extracts the archive of a GitHub repository's branch.
+///
+/// # Errors
+///
+/// This function will return an error if the archive could not be downloaded or extracted.
+pub async fn fetch_repo(repo: &str, branch: &str, dir: &Path) -> Result<()> {
+    let archive = Client::new()
+        .get(&format!(
+            "https://github.com/{repo}/archive/refs/heads/{branch}.zip"
+        ))
+        .send()
+        .await?
+        .error_for_status()
+        .context("Failed to fetch repository")?
+        .bytes()
+        .await?;
+
+    zip_extract::extract(Cursor::new(archive), dir, true).context("Failed to extract zip file")
+}
+
+/// Crawls a website, saving the main content of each page as a markdown file named after its path.
+///
+/// # Errors
+///
+/// This function will return an error if the base URL is invalid or the website could not be crawled.
+pub async fn crawl_website(
+    base_url: &str,
+    max_pages: Option<usize>,
+    public_only: bool,
+    dir: &Path,
+) -> Result<()> {
+    let mut website = Website::new(
+        base_url,
+        Config {
+            max_pages,
+            public_only,
+            ..Config::default()
+        },
+    )?;
+    let dir = dir.to_path_buf();
+
+    website
+        .crawl(move |url, html| {
+            let dir = dir.clone();
+
+            async move {
+                // Pages are processed in the background, so a page that fails is skipped instead of stopping the crawl.
+                if let Err(e) = save_page(&dir, &url, &html) {
+                    warn!("Failed to save page {url}: {e}");
+                }
+            }
+        })
+        .await
+}
+
+fn save_page(dir: &Path, url: &url::Url, html: &str) -> Result<()> {
+    let path = url.path();
+    let doc = extract(&mut html.as_bytes(), url)?;
+    let mut markdown = parse_html(&doc.content);
+
+    if !doc.title.is_empty() {
+        markdown = format!("---\ntitle: \"{}\"\n---\n{}", doc.title, markdown);
+    }
+
+    if markdown.is_empty() {
+        debug!("Skipping empty page: {url}");
+        return Ok(());
+    }
+
+    let file_path = PathBuf::from(format!(
+        "{}{path}{}.md",
+        dir.display(),
+        if path.ends_with('/') { "index" } else { "" }
+    ));
+
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(file_path, markdown)?;
+
+    Ok(())
+}
+
+/// Whether documentation can be parsed from a file, judging by its extension.
+#[must_use]
+pub fn is_supported(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| {
+        EXTENSIONS.contains(&ext.to_str().unwrap_or_default())
+    })
+}
+
+/// Removes every file documentation can't be parsed from.
+///
+/// # Errors
+///
+/// This function will return an error if the directory could not be read or a file could not be removed.
+pub fn remove_unsupported_files<P: AsRef<Path>>(path: P) -> Result<()> {
+    for file in read_dir_recursive(path)? {
+        if !is_supported(&file.path()) {
+            fs::remove_file(file.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses every file in a directory into a document, skipping the ones without any sections.
+///
+/// # Errors
+///
+/// This function will return an error if the directory could not be read or one of its files could not be parsed.
+pub fn parse_documents(dir: &Path, config: &ChunkConfig) -> Result<Vec<Document>> {
+    let base_path = dir
+        .to_str()
+        .ok_or_else(|| anyhow!("Failed to convert path to string"))?;
+
+    let mut documents = Vec::new();
+    for file in read_dir_recursive(dir)? {
+        let document = into_document(&file, base_path.to_string(), config)?;
+
+        if !document.sections.is_empty() {
+            documents.push(document);
+        }
+    }
+
+    Ok(documents)
+}
+
+/// Lists every file in a directory and its subdirectories.
+///
+/// # Errors
+///
+/// This function will return an error if one of the directories could not be read.
+pub fn read_dir_recursive<P: AsRef<Path>>(path: P) -> Result<Vec<DirEntry>> {
+    let files = fs::read_dir(path)?.collect::<Result<Vec<_>, std::io::Error>>()?;
+
+    Ok(files
+        .into_iter()
+        .flat_map(|entry| {
+            if entry.path().is_dir() {
+                read_dir_recursive(entry.path())
+            } else {
+                Ok(vec![entry])
+            }
+        })
+        .flatten()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_files_matching_the_globs() {
+        // Given
+        let dir = std::env::temp_dir().join(format!("clippy-train-{}", std::process::id()));
+        fs::create_dir_all(dir.join("guides")).unwrap();
+        fs::create_dir_all(dir.join("blog")).unwrap();
+        fs::write(
+            dir.join("guides/install.md"),
+            "# Install\n\nRun the installer.",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("guides/changelog.md"),
+            "# Changelog\n\nFixed bugs.",
+        )
+        .unwrap();
+        fs::write(dir.join("blog/launch.md"), "# Launch\n\nWe launched.").unwrap();
+        fs::write(dir.join("logo.png"), "").unwrap();
+
+        let source = Source {
+            include: vec!["guides/**".to_string()],
+            exclude: vec!["**/changelog.md".to_string()],
+            ..Source::from(SourceKind::Website {
+                url: "https://docs.example.com".to_string(),
+                max_pages: None,
+            })
+        };
+
+        // When
+        source.remove_unwanted_files(&dir).unwrap();
+        let documents = parse_documents(&dir, &ChunkConfig::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Then
+        assert_eq!(
+            documents
+                .iter()
+                .map(|d| d.path.as_str())
+                .collect::<Vec<_>>(),
+            vec!["/guides/install"]
+        );
+    }
+
+    #[test]
+    fn retains_changed_files() {
+        // Given
+        let dir = std::env::temp_dir().join(format!("clippy-changes-{}", std::process::id()));
+        fs::create_dir_all(dir.join("guides")).unwrap();
+        fs::write(dir.join("guides/install.md"), "# Install").unwrap();
+        fs::write(dir.join("guides/upgrade.md"), "# Upgrade").unwrap();
+
+        let source = Source::from(SourceKind::GitHub {
+            repo: "clippy/docs".to_string(),
+            branch: default_branch(),
+            path: Some("docs".to_string()),
+        });
+
+        // When
+        let removed = source
+            .retain_changed(
+                &dir,
+                &[
+                    "docs/guides/install.md".to_string(),
+                    "docs/guides/deploy.mdx".to_string(),
+                    "docs/logo.png".to_string(),
+                    "README.md".to_string(),
+                ],
+            )
+            .unwrap();
+        let files = read_dir_recursive(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|file| file.path())
+            .collect::<Vec<_>>();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Then
+        assert_eq!(files, vec![dir.join("guides/install.md")]);
+        assert_eq!(removed, vec!["/guides/deploy"]);
+    }
+
+    #[test]
+    fn identifies_sources_by_slug() {
+        // Given
+        let repo = |path: Option<&str>| {
+            Source::from(SourceKind::GitHub {
+                repo: "clippy/docs".to_string(),
+                branch: default_branch(),
+                path: path.map(ToString::to_string),
+            })
+        };
+        let website = Source::from(SourceKind::Website {
+            url: "https://docs.example.com/".to_string(),
+            max_pages: None,
+        });
+        let archive = Source::from(SourceKind::Archive {
+            path: PathBuf::from("storage/archives/src_1.zip"),
+        });
+
+        // Then
+        assert_eq!(repo(None).slug(), "clippy/docs/main");
+        assert_eq!(repo(Some("/docs/en/")).slug(), "clippy/docs/main/docs/en");
+        assert_eq!(website.slug(), "docs.example.com");
+        assert_eq!(archive.slug(), "src_1");
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_repository() {
+        let source = |path: &str| {
+            Source::from(SourceKind::GitHub {
+                repo: "clippy/docs".to_string(),
+                branch: default_branch(),
+                path: Some(path.to_string()),
+            })
+        };
+
+        assert!(source("docs/en").validate().is_ok());
+        assert!(source("../secrets").validate().is_err());
+    }
+}
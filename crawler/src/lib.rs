@@ -1,15 +1,16 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use dom_manipulator::{Html, Selector};
 use futures_util::{Future, StreamExt};
 use reqwest::{
     header::{self, HeaderMap, HeaderValue},
-    Client,
+    redirect, Client, ClientBuilder,
 };
 use std::{
     collections::HashSet,
     fmt::Display,
+    net::IpAddr,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
@@ -17,6 +18,7 @@ use std::{
     time::Duration,
 };
 use tokio::{
+    net::lookup_host,
     sync::{mpsc, Barrier},
     time::sleep,
 };
@@ -32,6 +34,10 @@ pub struct Config {
     pub user_agent: String,
     pub crawling_concurrency: usize,
     pub processing_concurrency: usize,
+    /// The number of pages to crawl at most. Links found after reaching it are skipped.
+    pub max_pages: Option<usize>,
+    /// Only crawls websites whose host resolves to public addresses, so the crawler can't be pointed at internal services.
+    pub public_only: bool,
 }
 
 impl Default for Config {
@@ -39,6 +45,8 @@ impl Default for Config {
         Self {
             crawling_concurrency: 10,
             processing_concurrency: 10,
+            max_pages: None,
+            public_only: false,
             delay: Duration::from_millis(5),
             user_agent: "ClippyBot/0.1.0 (clippy.help)".to_string(),
         }
@@ -51,6 +59,7 @@ pub enum SkipReason {
     Blacklisted,
     HostMismatch,
     OutsideBasePath,
+    LimitReached,
 }
 
 impl Display for SkipReason {
@@ -60,6 +69,7 @@ impl Display for SkipReason {
             Self::HostMismatch => write!(f, "Host mismatch"),
             Self::AlreadyVisited => write!(f, "Already visited"),
             Self::OutsideBasePath => write!(f, "Outside base path"),
+            Self::LimitReached => write!(f, "Page limit reached"),
         }
     }
 }
@@ -79,25 +89,51 @@ impl Website {
     ///
     /// Will throw an error if the base url is invalid
     pub fn new(base_url: &str, config: Config) -> Result<Self> {
-        let mut headers = HeaderMap::new();
-        headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive"));
-
-        let client = Client::builder()
-            .gzip(true)
-            .brotli(true)
-            .default_headers(headers)
-            .pool_idle_timeout(None)
-            .user_agent(&config.user_agent)
-            .tcp_keepalive(Duration::from_millis(500));
-
         Ok(Self {
+            client: client_builder(&config).build()?,
             config,
-            client: client.build()?,
             visited_urls: HashSet::new(),
             base_url: Url::parse(base_url)?,
         })
     }
 
+    /// Pins the client to the addresses the base host resolves to, making sure they're public, and keeps redirects on that host.
+    ///
+    /// # Errors
+    ///
+    /// Will throw an error if the host can't be resolved or resolves to a non-public address
+    async fn pin_public_host(&mut self) -> Result<()> {
+        let host = self
+            .base_url
+            .host_str()
+            .ok_or_else(|| anyhow!("The base url has no host"))?
+            .to_string();
+        let port = self.base_url.port_or_known_default().unwrap_or(80);
+
+        let addrs = lookup_host((host.as_str(), port))
+            .await?
+            .collect::<Vec<_>>();
+        if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+            bail!("{host} doesn't resolve to a public address");
+        }
+
+        let redirect_host = host.clone();
+        self.client = client_builder(&self.config)
+            .resolve(&host, addrs[0])
+            .redirect(redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= 10 {
+                    attempt.error("Too many redirects")
+                } else if attempt.url().host_str() == Some(redirect_host.as_str()) {
+                    attempt.follow()
+                } else {
+                    attempt.stop()
+                }
+            }))
+            .build()?;
+
+        Ok(())
+    }
+
     /// Launches the processors that will process the pages and send them to the `on_page` callback
     ///
     /// # Errors
@@ -108,6 +144,10 @@ impl Website {
         F: (Fn(Url, String) -> Fut) + Send + Sync + 'static,
         Fut: Future<Output = ()> + Send + 'static,
     {
+        if self.config.public_only {
+            self.pin_public_host().await?;
+        }
+
         let crawling_queue_capacity = self.config.crawling_concurrency * 400;
         let processing_queue_capacity = self.config.processing_concurrency * 10;
         let active_spiders = Arc::new(AtomicUsize::new(0));
@@ -263,10 +303,66 @@ impl Website {
             return Err(SkipReason::Blacklisted);
         }
 
+        if self
+            .config
+            .max_pages
+            .map_or(false, |max| self.visited_urls.len() >= max)
+        {
+            return Err(SkipReason::LimitReached);
+        }
+
         Ok(())
     }
 }
 
+fn client_builder(config: &Config) -> ClientBuilder {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive"));
+
+    Client::builder()
+        .gzip(true)
+        .brotli(true)
+        .default_headers(headers)
+        .pool_idle_timeout(None)
+        .user_agent(&config.user_agent)
+        .tcp_keepalive(Duration::from_millis(500))
+}
+
+/// Whether an address is reachable from the internet, as opposed to loopback, private, link-local and other reserved ranges.
+#[must_use]
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // Shared address space, used by carrier-grade NATs.
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local addresses.
+                || (first & 0xfe00) == 0xfc00
+                // Link-local addresses.
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
 fn clean_url(url: &str, base_url: &Url) -> Url {
     let mut url = base_url.join(url).unwrap();
 